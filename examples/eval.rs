@@ -0,0 +1,59 @@
+// recall@k, qps and latency for hnsw over a sweep of ef, m and ef_construction
+//
+// cargo run --release --example eval -- <data.json|data.parquet> [ground truth jsonl] [l2|dot]
+//
+// parquet files need an `emb` column, without a ground truth file it gets
+// computed by brute force for the first QUERIES vectors. the metric (l2 by
+// default) is used for the ground truth and the index both, so the output of
+// `examples/simple.rs` (sorted by dot product) needs `dot`
+
+use std::{env, path::Path};
+
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SimpleDotProduct, SquaredEuclidean},
+    eval::{self, Dataset, GroundTruth, Sweep},
+};
+
+const QUERIES: usize = 200;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let data_path = args.next().unwrap_or("test_data.json".to_string());
+    let truth_path = args.next();
+    let metric = args.next();
+    let dist_calc = || -> Box<dyn DistanceCalculator> {
+        match metric.as_deref() {
+            Some("dot") => Box::new(SimpleDotProduct {}),
+            Some("l2") | None => Box::new(SquaredEuclidean {}),
+            Some(m) => panic!("unknown metric {m}"),
+        }
+    };
+
+    let dataset = Dataset::from_path(Path::new(&data_path), "emb").unwrap();
+    println!(
+        "{} vectors, dim {}",
+        dataset.vectors.len(),
+        dataset.dimension()
+    );
+
+    let sweep = Sweep {
+        k: 10,
+        efs: vec![10, 20, 40, 80, 160],
+        ms: vec![8, 16, 24],
+        ef_constructions: vec![50, 100],
+    };
+
+    let truth = match truth_path {
+        Some(p) => {
+            GroundTruth::load_jsonl(Path::new(&p), &dataset, sweep.k, dist_calc().as_mut()).unwrap()
+        }
+        None => {
+            let queries = (0..usize::min(QUERIES, dataset.vectors.len())).collect::<Vec<_>>();
+            GroundTruth::compute(&dataset, &queries, sweep.k, dist_calc().as_mut())
+        }
+    };
+
+    for result in eval::run(&dataset, &truth, &sweep, dist_calc).unwrap() {
+        println!("{result}");
+    }
+}
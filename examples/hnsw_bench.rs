@@ -0,0 +1,166 @@
+// times building and searching an in memory HNSW, against a brute force
+// FlatIndex over the same vectors as the baseline (for recall and qps)
+//
+// cargo run --release --example hnsw_bench -- [data path] [queries]
+//
+// without a data path the vectors get generated, 20k 64-dim vectors in 200
+// tight clusters from a fixed seed, so runs on different commits see the same
+// data. a data path is read like simple.rs reads test_data.json
+//
+// generated data, 1000 queries, one core, tinyworld built at opt-level 3:
+//
+//                                  inserts/s   search qps   search_batch qps
+//   HashMap<Uuid, ..> adjacency       ~200         ~670           ~710
+//   dense u32 ids                      357         1608           1702
+//
+// the first row is the commit before the switch to dense ids, the second is
+// the switch itself. both are a worktree of that commit with this file copied
+// into examples/ (and the leftover debug printlns in hnsw.rs taken out, they
+// swamp the timings), run with
+// CARGO_PROFILE_DEV_PACKAGE_tinyworld_OPT_LEVEL=3 cargo run --example hnsw_bench
+// recall@10 was 0.99 for both. the first row is the mean of two runs. the
+// same build of the current tree did 311 inserts/s and 1818 qps
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tinyworld::{
+    distance_calculators::SquaredEuclidean,
+    flat::FlatIndex,
+    hnsw::{FixedParams, HNSW},
+};
+
+#[derive(Deserialize)]
+struct TestInData {
+    #[allow(dead_code)]
+    word: String,
+    emb: Vec<f32>,
+}
+
+const TOP_K: usize = 10;
+const EF: usize = 64;
+
+fn clustered(n: usize, clusters: usize, dim: usize) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(43);
+    let centers: Vec<Vec<f32>> = (0..clusters)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-10.0..10.0)).collect())
+        .collect();
+    (0..n)
+        .map(|i| {
+            centers[i % clusters]
+                .iter()
+                .map(|c| c + rng.gen_range(-1.0..1.0))
+                .collect()
+        })
+        .collect()
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let data_path = args.next();
+    let num_queries = match args.next() {
+        Some(q) => q.parse().unwrap(),
+        None => 1000,
+    };
+
+    let data = match &data_path {
+        Some(path) => {
+            let file = File::open(path).unwrap();
+            let rdr = BufReader::new(file);
+            rdr.lines()
+                .map(|l| serde_json::from_str::<TestInData>(&l.unwrap()).unwrap().emb)
+                .collect::<Vec<Vec<f32>>>()
+        }
+        None => clustered(20_000, 200, 64),
+    };
+    let dim = data[0].len();
+    let queries = data
+        .iter()
+        .cycle()
+        .take(num_queries)
+        .map(|d| d.as_slice())
+        .collect::<Vec<_>>();
+    println!(
+        "{} vectors, dim {dim}, {} queries",
+        data.len(),
+        queries.len()
+    );
+
+    let mut flat = FlatIndex::new(dim as u32, RefCell::new(Box::new(SquaredEuclidean {})));
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: dim as u32,
+            m: 16,
+            m_max: 16,
+            m0_max: 32,
+            ef_construction: 100,
+            level_norm: 1.0 / f32::ln(16.0),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    // flat and hnsw ids for the same vector
+    let mut same = HashMap::new();
+    let start = Instant::now();
+    for d in &data {
+        let id = hnsw.insert(d).unwrap();
+        same.insert(flat.insert(d).unwrap(), id);
+    }
+    let build = start.elapsed();
+    println!(
+        "build: {build:.2?}, {:.0} inserts/s",
+        data.len() as f64 / build.as_secs_f64()
+    );
+
+    let start = Instant::now();
+    let found = queries
+        .iter()
+        .map(|q| hnsw.search(q, TOP_K, EF).unwrap())
+        .collect::<Vec<_>>();
+    let search = start.elapsed();
+    println!(
+        "search: {:.2?}/query, {:.0} qps",
+        search / queries.len() as u32,
+        queries.len() as f64 / search.as_secs_f64()
+    );
+
+    let start = Instant::now();
+    hnsw.search_batch(&queries, TOP_K, EF).unwrap();
+    let batch = start.elapsed();
+    println!(
+        "search_batch: {:.2?}/query, {:.0} qps",
+        batch / queries.len() as u32,
+        queries.len() as f64 / batch.as_secs_f64()
+    );
+
+    let start = Instant::now();
+    let expected = queries
+        .iter()
+        .map(|q| flat.search(q, TOP_K, 0).unwrap())
+        .collect::<Vec<_>>();
+    let brute = start.elapsed();
+    let hits: usize = found
+        .iter()
+        .zip(&expected)
+        .map(|(f, e)| {
+            e.iter()
+                .filter(|(id, _)| f.iter().any(|(h, _)| *h == same[id]))
+                .count()
+        })
+        .sum();
+    println!(
+        "brute force: {:.2?}/query, {:.0} qps, hnsw is {:.1}x faster at recall@{TOP_K} {:.4}",
+        brute / queries.len() as u32,
+        queries.len() as f64 / brute.as_secs_f64(),
+        brute.as_secs_f64() / search.as_secs_f64(),
+        hits as f64 / (queries.len() * TOP_K) as f64
+    );
+}
@@ -0,0 +1,127 @@
+// compares recall of the pq encoding against plain f32 vectors on the word
+// embeddings in test_data.json (same format simple.rs reads)
+//
+// cargo run --release --example pq_recall -- [data path] [subspaces]
+
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+    time::Instant,
+};
+
+use serde::Deserialize;
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SquaredEuclidean},
+    hnsw::FixedParams,
+    quantization::{Encoding, ProductQuantizer},
+    tinyworld::TinyWorld,
+};
+
+#[derive(Deserialize)]
+struct TestInData {
+    #[allow(dead_code)]
+    word: String,
+    emb: Vec<f32>,
+}
+
+const TOP_K: usize = 10;
+const EF: usize = 64;
+const QUERIES: usize = 200;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let data_path = args.next().unwrap_or("test_data.json".to_string());
+
+    let data = {
+        let file = File::open(&data_path).unwrap();
+        let rdr = BufReader::new(file);
+        rdr.lines()
+            .map(|l| serde_json::from_str::<TestInData>(&l.unwrap()).unwrap().emb)
+            .collect::<Vec<Vec<f32>>>()
+    };
+    let dim = data[0].len();
+    let subspaces = match args.next() {
+        Some(m) => m.parse().unwrap(),
+        None => (1..=usize::max(1, dim / 4))
+            .rev()
+            .find(|m| dim.is_multiple_of(*m))
+            .unwrap(),
+    };
+    let queries = &data[..usize::min(QUERIES, data.len())];
+    println!(
+        "{} vectors, dim {dim}, {} queries",
+        data.len(),
+        queries.len()
+    );
+
+    // brute force ground truth
+    let mut calc = SquaredEuclidean {};
+    let truth = queries
+        .iter()
+        .map(|q| {
+            let mut dists = data
+                .iter()
+                .enumerate()
+                .map(|(i, d)| (i, calc.calc_dist(q, d)))
+                .collect::<Vec<_>>();
+            dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+            dists.truncate(TOP_K);
+            dists.into_iter().map(|(i, _)| i).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let sample = data.iter().map(|d| d.as_slice()).collect::<Vec<_>>();
+    let train_pq = || Encoding::PQ(ProductQuantizer::train(&sample, dim, subspaces, 10).unwrap());
+    let runs = [
+        ("f32", Encoding::F32, false),
+        ("pq", train_pq(), false),
+        ("pq + re-rank", train_pq(), true),
+    ];
+
+    for (name, encoding, keep_raw) in runs {
+        let path = env::temp_dir().join(format!("pq_recall_{}.tw", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let start = Instant::now();
+        let mut tw = TinyWorld::create_with_encoding(
+            path,
+            FixedParams {
+                dimension: dim as u32,
+                ..Default::default()
+            },
+            Box::new(SquaredEuclidean {}),
+            encoding,
+            keep_raw,
+        )
+        .unwrap();
+        let ids = data
+            .iter()
+            .map(|d| tw.insert(d).unwrap())
+            .collect::<Vec<_>>();
+        tw.flush().unwrap();
+        let build = start.elapsed();
+        let size = std::fs::metadata(path).unwrap().len();
+
+        let start = Instant::now();
+        let mut hits = 0;
+        for (q, t) in queries.iter().zip(&truth) {
+            let found = tw.search(q, TOP_K, EF).unwrap();
+            hits += found
+                .iter()
+                .filter(|(id, _)| t.iter().any(|i| ids[*i] == *id))
+                .count();
+        }
+        let search = start.elapsed();
+
+        println!(
+            "{name:>14}: recall@{TOP_K} {:.3}, build {build:.2?}, search {:.2?}/query, file {size} bytes",
+            hits as f32 / (queries.len() * TOP_K) as f32,
+            search / queries.len() as u32,
+        );
+
+        drop(tw);
+        std::fs::remove_file(path).unwrap();
+        let _ = std::fs::remove_file(path.replace(".tw", ".raw.tw"));
+    }
+}
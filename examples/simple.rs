@@ -1,14 +1,10 @@
 use std::{
-    cmp::Reverse,
-    collections::{BTreeMap, BinaryHeap},
     fs::File,
     io::{BufRead, BufReader, LineWriter, Write},
 };
 
-use ordered_float::OrderedFloat;
-use parquet::format::XxHash;
 use serde::{Deserialize, Serialize};
-use sorted_vec::partial::{ReverseSortedVec, SortedVec};
+use sorted_vec::partial::SortedVec;
 use tinyworld::distance_calculators::{DistanceCalculator, SimpleDotProduct};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -42,7 +38,7 @@ impl PartialEq for WordDist {
 }
 
 fn main() {
-    let mut data = {
+    let data = {
         let file = File::open("test_data.json").unwrap();
         let rdr = BufReader::new(file);
         rdr.lines()
@@ -54,7 +50,8 @@ fn main() {
     let mut writer = LineWriter::new(out_file);
     let mut calc = SimpleDotProduct {};
     for datum in &data {
-        let mut dists = ReverseSortedVec::new();
+        // smallest distance (biggest dot product) first
+        let mut dists = SortedVec::new();
 
         for d in &data {
             let dist = calc.calc_dist(&datum.emb, &d.emb);
@@ -78,9 +75,9 @@ fn main() {
             matches: dists.iter().map(|x| x.word.clone()).collect(),
         })
         .unwrap();
-        l.push_str("\n");
+        l.push('\n');
 
-        writer.write(l.as_bytes()).unwrap();
+        writer.write_all(l.as_bytes()).unwrap();
 
         // ms.push(dist_map.into_values().collect::<Vec<String>>());
     }
@@ -1,14 +1,183 @@
 #[cfg(feature = "simd")]
 use std::simd::f32x4;
 
+use half::{bf16, f16};
+
+use crate::quantization::ScalarQuantizer;
+
 pub trait DistanceCalculator {
     fn calc_dist(&mut self, a: &[f32], b: &[f32]) -> f32;
+
+    /// which metric this is, it gets written into `.tw` headers so a file can't
+    /// be opened with a different one than it was built with. 0 isn't used
+    fn id(&self) -> u32;
+
+    /// turns a distance from this calculator into a similarity score where
+    /// bigger is more similar, by default thats just the negated distance
+    fn score(&self, dist: f32) -> f32 {
+        -dist
+    }
+
+    /// another calculator just like this one that can go to another thread, for
+    /// searching in parallel. the default is none, which means searches that
+    /// want to go parallel end up running one after another on this one
+    fn new_instance(&self) -> Option<Box<dyn DistanceCalculator + Send>> {
+        None
+    }
+
+    /// distance between a full precision query and an int8 code, by default
+    /// this just decodes the code and uses [`DistanceCalculator::calc_dist`]
+    fn calc_dist_sq8(&mut self, query: &[f32], code: &[u8], sq: &ScalarQuantizer) -> f32 {
+        let mut decoded = vec![0.0; code.len()];
+        sq.decode(code, &mut decoded);
+        self.calc_dist(query, &decoded)
+    }
+
+    /// distance between a full precision query and a half precision vector, by
+    /// default this widens the whole vector first, override it to widen on the fly
+    fn calc_dist_f16(&mut self, query: &[f32], v: &[f16]) -> f32 {
+        let widened = v.iter().map(|x| x.to_f32()).collect::<Vec<_>>();
+        self.calc_dist(query, &widened)
+    }
+
+    fn calc_dist_bf16(&mut self, query: &[f32], v: &[bf16]) -> f32 {
+        let widened = v.iter().map(|x| x.to_f32()).collect::<Vec<_>>();
+        self.calc_dist(query, &widened)
+    }
+}
+
+fn dot<T: Copy + Into<f32>>(a: &[f32], b: &[T]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * (*b).into()).sum()
 }
 
+fn squared_euclidean<T: Copy + Into<f32>>(a: &[f32], b: &[T]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| {
+            let d = a - (*b).into();
+            d * d
+        })
+        .sum()
+}
+
+// negated, so that like every other calculator smaller is closer. the default
+// score flips it back into the dot product
 pub struct SimpleDotProduct {}
 impl DistanceCalculator for SimpleDotProduct {
     fn calc_dist(&mut self, a: &[f32], b: &[f32]) -> f32 {
-        a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+        -dot(a, b)
+    }
+
+    fn id(&self) -> u32 {
+        2
+    }
+
+    fn new_instance(&self) -> Option<Box<dyn DistanceCalculator + Send>> {
+        Some(Box::new(SimpleDotProduct {}))
+    }
+
+    fn calc_dist_f16(&mut self, query: &[f32], v: &[f16]) -> f32 {
+        -dot(query, v)
+    }
+
+    fn calc_dist_bf16(&mut self, query: &[f32], v: &[bf16]) -> f32 {
+        -dot(query, v)
+    }
+
+    fn calc_dist_sq8(&mut self, query: &[f32], code: &[u8], sq: &ScalarQuantizer) -> f32 {
+        -query
+            .iter()
+            .zip(code)
+            .zip(sq.mins().iter().zip(sq.scales()))
+            .map(|((q, c), (min, scale))| q * (min + (*c as f32 * scale)))
+            .sum::<f32>()
+    }
+}
+
+pub struct SquaredEuclidean {}
+impl DistanceCalculator for SquaredEuclidean {
+    fn calc_dist(&mut self, a: &[f32], b: &[f32]) -> f32 {
+        squared_euclidean(a, b)
+    }
+
+    fn id(&self) -> u32 {
+        1
+    }
+
+    fn new_instance(&self) -> Option<Box<dyn DistanceCalculator + Send>> {
+        Some(Box::new(SquaredEuclidean {}))
+    }
+
+    // 1 for the same vector, down towards 0 the further apart they are
+    fn score(&self, dist: f32) -> f32 {
+        1.0 / (1.0 + dist)
+    }
+
+    fn calc_dist_f16(&mut self, query: &[f32], v: &[f16]) -> f32 {
+        squared_euclidean(query, v)
+    }
+
+    fn calc_dist_bf16(&mut self, query: &[f32], v: &[bf16]) -> f32 {
+        squared_euclidean(query, v)
+    }
+
+    fn calc_dist_sq8(&mut self, query: &[f32], code: &[u8], sq: &ScalarQuantizer) -> f32 {
+        query
+            .iter()
+            .zip(code)
+            .zip(sq.mins().iter().zip(sq.scales()))
+            .map(|((q, c), (min, scale))| {
+                let d = q - (min + (*c as f32 * scale));
+                d * d
+            })
+            .sum()
+    }
+}
+
+/// hamming distance between the signs of two vectors, see
+/// [`crate::quantization::binarize`]
+pub struct Hamming {}
+impl DistanceCalculator for Hamming {
+    /// reference version on unpacked f32s, one compare per dimension. stored
+    /// binary codes never go through here, [`crate::quantization::Encoding::calc_dist`]
+    /// uses [`Hamming::calc_dist_bits`] on the packed bits
+    fn calc_dist(&mut self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .filter(|(a, b)| (**a > 0.0) != (**b > 0.0))
+            .count() as f32
+    }
+
+    fn id(&self) -> u32 {
+        3
+    }
+
+    fn new_instance(&self) -> Option<Box<dyn DistanceCalculator + Send>> {
+        Some(Box::new(Hamming {}))
+    }
+}
+
+impl Hamming {
+    /// hamming distance between two packed bit codes
+    pub fn calc_dist_bits(a: &[u8], b: &[u8]) -> f32 {
+        let a_chunks = a.chunks_exact(8);
+        let b_chunks = b.chunks_exact(8);
+        let tail = a_chunks
+            .remainder()
+            .iter()
+            .zip(b_chunks.remainder())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>();
+
+        a_chunks
+            .zip(b_chunks)
+            .map(|(a, b)| {
+                let a = u64::from_ne_bytes(a.try_into().unwrap());
+                let b = u64::from_ne_bytes(b.try_into().unwrap());
+                (a ^ b).count_ones()
+            })
+            .sum::<u32>() as f32
+            + tail as f32
     }
 }
 
@@ -23,4 +192,9 @@ impl DistanceCalculator for SimdDotProduct {
         let a_chunks = a.chunks_exact(4);
         let b_chunks = b.chunks_exact(4);
     }
+
+    // same metric as SimpleDotProduct
+    fn id(&self) -> u32 {
+        2
+    }
 }
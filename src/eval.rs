@@ -0,0 +1,385 @@
+// recall evaluation against brute force ground truth, for tuning `ef`, `m` and
+// `ef_construction`. see `examples/eval.rs` for running it on a dataset
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use arrow::{
+    array::{Array, StringArray},
+    error::ArrowError,
+};
+use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, errors::ParquetError};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    distance_calculators::DistanceCalculator,
+    hnsw::{self, FixedParams, HNSW},
+    import,
+};
+
+#[derive(Debug)]
+pub enum EvalError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    ParquetError(ParquetError),
+    ArrowError(ArrowError),
+    ColumnError(String),
+    UnknownKey(String),
+    // the ground truth for this query isn't in order by the distance calculator
+    // being evaluated, so it was made with some other metric
+    MetricMismatch(String),
+    // a vector that isn't as long as the first one, by its key
+    DimensionError {
+        key: String,
+        expected: usize,
+        found: usize,
+    },
+    IndexError(hnsw::Error),
+}
+
+impl From<io::Error> for EvalError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<serde_json::Error> for EvalError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::JsonError(e)
+    }
+}
+
+impl From<ParquetError> for EvalError {
+    fn from(e: ParquetError) -> Self {
+        Self::ParquetError(e)
+    }
+}
+
+impl From<ArrowError> for EvalError {
+    fn from(e: ArrowError) -> Self {
+        Self::ArrowError(e)
+    }
+}
+
+impl From<hnsw::Error> for EvalError {
+    fn from(e: hnsw::Error) -> Self {
+        Self::IndexError(e)
+    }
+}
+
+/// a set of named vectors to build an index from
+pub struct Dataset {
+    pub keys: Vec<String>,
+    pub vectors: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+struct JsonRow {
+    word: String,
+    emb: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct JsonTruthRow {
+    word: String,
+    matches: Vec<String>,
+}
+
+impl Dataset {
+    /// one `{"word": .., "emb": [..]}` object per line, the same format
+    /// `examples/simple.rs` reads
+    pub fn from_jsonl(path: &Path) -> Result<Self, EvalError> {
+        let rdr = BufReader::new(File::open(path)?);
+        let mut keys = vec![];
+        let mut vectors = vec![];
+        for line in rdr.lines() {
+            let row = serde_json::from_str::<JsonRow>(&line?)?;
+            keys.push(row.word);
+            vectors.push(row.emb);
+        }
+
+        Self { keys, vectors }.checked()
+    }
+
+    /// reads `vector_column` (a list or fixed size list of f32) and optionally
+    /// `key_column` (strings, otherwise keys are just row numbers)
+    pub fn from_parquet(
+        path: &Path,
+        vector_column: &str,
+        key_column: Option<&str>,
+    ) -> Result<Self, EvalError> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+        let mut keys = vec![];
+        let mut vectors = vec![];
+        for batch in reader {
+            let batch = batch?;
+            match batch
+                .column_by_name(vector_column)
+                .and_then(|c| import::vector_rows(c.as_ref()))
+            {
+                Some(rows) => vectors.extend(rows.into_iter().map(|r| r.to_vec())),
+                None => return Err(EvalError::ColumnError(vector_column.to_string())),
+            }
+
+            match key_column {
+                Some(name) => {
+                    let column = batch
+                        .column_by_name(name)
+                        .and_then(|c| c.as_any().downcast_ref::<StringArray>());
+                    match column {
+                        Some(c) => keys.extend(c.iter().map(|k| k.unwrap_or("").to_string())),
+                        None => return Err(EvalError::ColumnError(name.to_string())),
+                    }
+                }
+                None => keys.extend((keys.len()..vectors.len()).map(|i| i.to_string())),
+            }
+        }
+
+        Self { keys, vectors }.checked()
+    }
+
+    pub fn from_path(path: &Path, vector_column: &str) -> Result<Self, EvalError> {
+        match path.extension() {
+            Some(e) if e == "parquet" => Self::from_parquet(path, vector_column, None),
+            _ => Self::from_jsonl(path),
+        }
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.vectors.first().map(|v| v.len()).unwrap_or(0)
+    }
+
+    // every vector has to be as long as the first one
+    fn checked(self) -> Result<Self, EvalError> {
+        let dimension = self.dimension();
+        match self.vectors.iter().position(|v| v.len() != dimension) {
+            Some(i) => Err(EvalError::DimensionError {
+                key: self.keys[i].clone(),
+                expected: dimension,
+                found: self.vectors[i].len(),
+            }),
+            None => Ok(self),
+        }
+    }
+}
+
+/// the true nearest neighbors (as indexes into the dataset, nearest first) for
+/// each query
+pub struct GroundTruth {
+    pub queries: Vec<usize>,
+    pub neighbors: Vec<Vec<usize>>,
+}
+
+impl GroundTruth {
+    /// brute force, every query against every vector
+    pub fn compute(
+        dataset: &Dataset,
+        queries: &[usize],
+        k: usize,
+        dist_calc: &mut dyn DistanceCalculator,
+    ) -> Self {
+        let neighbors = queries
+            .iter()
+            .map(|q| {
+                let query = &dataset.vectors[*q];
+                let mut dists = dataset
+                    .vectors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (i, dist_calc.calc_dist(query, v)))
+                    .collect::<Vec<_>>();
+                dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+                dists.into_iter().take(k).map(|(i, _)| i).collect()
+            })
+            .collect();
+
+        Self {
+            queries: queries.to_vec(),
+            neighbors,
+        }
+    }
+
+    /// loads ground truth in the `test_data_temp.json` format `examples/simple.rs`
+    /// writes, one `{"word": .., "matches": [..]}` per line. only the first `k`
+    /// matches are kept, and they need to be in the same order `dist_calc` sorts
+    /// them (nearest first), anything else is a [`EvalError::MetricMismatch`]
+    pub fn load_jsonl(
+        path: &Path,
+        dataset: &Dataset,
+        k: usize,
+        dist_calc: &mut dyn DistanceCalculator,
+    ) -> Result<Self, EvalError> {
+        let key_to_idx: HashMap<&str, usize> = dataset
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.as_str(), i))
+            .collect();
+        let lookup = |key: &str| match key_to_idx.get(key) {
+            Some(i) => Ok(*i),
+            None => Err(EvalError::UnknownKey(key.to_string())),
+        };
+
+        let rdr = BufReader::new(File::open(path)?);
+        let mut queries = vec![];
+        let mut neighbors = vec![];
+        for line in rdr.lines() {
+            let row = serde_json::from_str::<JsonTruthRow>(&line?)?;
+            let query = lookup(&row.word)?;
+            let matches = row
+                .matches
+                .iter()
+                .take(k)
+                .map(|m| lookup(m))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let dists = matches
+                .iter()
+                .map(|m| dist_calc.calc_dist(&dataset.vectors[query], &dataset.vectors[*m]))
+                .collect::<Vec<_>>();
+            // a little slack for ties that came out in a different order
+            if dists
+                .windows(2)
+                .any(|w| w[0] > w[1] + (1e-4 * f32::max(1.0, w[1].abs())))
+            {
+                return Err(EvalError::MetricMismatch(row.word));
+            }
+
+            queries.push(query);
+            neighbors.push(matches);
+        }
+
+        Ok(Self { queries, neighbors })
+    }
+}
+
+/// the grid of parameters to evaluate, every combination gets run. indexes are
+/// built once per (`m`, `ef_construction`) and searched with every `ef`
+pub struct Sweep {
+    pub k: usize,
+    pub efs: Vec<usize>,
+    pub ms: Vec<u32>,
+    pub ef_constructions: Vec<u32>,
+}
+
+pub struct EvalResult {
+    pub m: u32,
+    pub ef_construction: u32,
+    pub ef: usize,
+    pub k: usize,
+    pub recall: f32,
+    pub build_time: Duration,
+    pub qps: f32,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl fmt::Display for EvalResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "m {:>3} ef_construction {:>4} ef {:>4} | recall@{} {:.4} | build {:>8.2?} | qps {:>9.1} | p50 {:>9.2?} p95 {:>9.2?} p99 {:>9.2?}",
+            self.m,
+            self.ef_construction,
+            self.ef,
+            self.k,
+            self.recall,
+            self.build_time,
+            self.qps,
+            self.p50,
+            self.p95,
+            self.p99,
+        )
+    }
+}
+
+/// builds an [`HNSW`] for every (`m`, `ef_construction`) in the sweep and
+/// measures recall and latency for every `ef`. `m_max` and `m0_max` follow the
+/// defaults' ratio to `m` (2x and 4x) and `level_norm` is 1/ln(m)
+pub fn run(
+    dataset: &Dataset,
+    truth: &GroundTruth,
+    sweep: &Sweep,
+    dist_calc: impl Fn() -> Box<dyn DistanceCalculator>,
+) -> Result<Vec<EvalResult>, EvalError> {
+    let mut results = vec![];
+
+    for m in &sweep.ms {
+        for ef_construction in &sweep.ef_constructions {
+            let mut hnsw = HNSW::new(
+                FixedParams {
+                    dimension: dataset.dimension() as u32,
+                    m: *m,
+                    m_max: m * 2,
+                    m0_max: m * 4,
+                    ef_construction: *ef_construction,
+                    level_norm: 1.0 / f32::ln(*m as f32),
+                    ..Default::default()
+                },
+                RefCell::new(dist_calc()),
+            );
+
+            let start = Instant::now();
+            let ids = dataset
+                .vectors
+                .iter()
+                .map(|v| hnsw.insert(v))
+                .collect::<Result<Vec<Uuid>, _>>()?;
+            let build_time = start.elapsed();
+            let id_to_idx: HashMap<Uuid, usize> =
+                ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+            for ef in &sweep.efs {
+                let mut latencies = Vec::with_capacity(truth.queries.len());
+                let mut hits = 0;
+                let mut total = 0;
+                for (q, expected) in truth.queries.iter().zip(&truth.neighbors) {
+                    let start = Instant::now();
+                    let found = hnsw.search(&dataset.vectors[*q], sweep.k, *ef)?;
+                    latencies.push(start.elapsed());
+
+                    let expected = &expected[..usize::min(sweep.k, expected.len())];
+                    hits += found
+                        .iter()
+                        .filter(|(id, _)| expected.contains(&id_to_idx[id]))
+                        .count();
+                    total += expected.len();
+                }
+
+                latencies.sort();
+                let total_time: Duration = latencies.iter().sum();
+                results.push(EvalResult {
+                    m: *m,
+                    ef_construction: *ef_construction,
+                    ef: *ef,
+                    k: sweep.k,
+                    recall: hits as f32 / usize::max(1, total) as f32,
+                    build_time,
+                    qps: latencies.len() as f32 / total_time.as_secs_f32(),
+                    p50: percentile(&latencies, 0.50),
+                    p95: percentile(&latencies, 0.95),
+                    p99: percentile(&latencies, 0.99),
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// `sorted` has to be sorted already
+fn percentile(sorted: &[Duration], p: f32) -> Duration {
+    match sorted.len() {
+        0 => Duration::ZERO,
+        n => sorted[usize::min(n - 1, (p * n as f32) as usize)],
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use uuid::Uuid;
+
+use crate::{distance_calculators::DistanceCalculator, hnsw::Error};
+
+/// exact search, every query gets compared against every vector. good for small
+/// collections, and as the ground truth for checking recall
+pub struct FlatIndex {
+    dimension: usize,
+    dist_calc: RefCell<Box<dyn DistanceCalculator>>,
+    ids: Vec<Uuid>,
+    // all the vectors back to back, in the same order as ids
+    data: Vec<f32>,
+    positions: HashMap<Uuid, usize>,
+}
+
+impl FlatIndex {
+    pub fn new(dimension: u32, dist_calc: RefCell<Box<dyn DistanceCalculator>>) -> Self {
+        Self {
+            dimension: dimension as usize,
+            dist_calc,
+            ids: vec![],
+            data: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    /// `ef` doesn't mean anything here, its just so the signature matches
+    /// [`crate::hnsw::HNSW::search`]
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        _ef: usize,
+    ) -> Result<Vec<(Uuid, f32)>, Error> {
+        if query.len() != self.dimension {
+            return Err(Error::EmbSizeError);
+        }
+        // chunks_exact panics on a zero dimension, which is also an empty index
+        if self.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut dist_calc = self.dist_calc.borrow_mut();
+        let mut out = self
+            .ids
+            .iter()
+            .zip(self.data.chunks_exact(self.dimension))
+            .map(|(id, v)| (*id, dist_calc.calc_dist(query, v)))
+            .collect::<Vec<_>>();
+
+        out.sort_by(|a, b| a.1.total_cmp(&b.1));
+        out.truncate(top_k);
+        Ok(out)
+    }
+
+    /// errors on a zero dimension index too, empty vectors can't be searched
+    pub fn insert(&mut self, new_data: &[f32]) -> Result<Uuid, Error> {
+        if self.dimension == 0 || new_data.len() != self.dimension {
+            return Err(Error::EmbSizeError);
+        }
+
+        let id = Uuid::new_v4();
+        self.positions.insert(id, self.ids.len());
+        self.ids.push(id);
+        self.data.extend_from_slice(new_data);
+
+        Ok(id)
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<&[f32]> {
+        self.positions
+            .get(&id)
+            .map(|pos| &self.data[pos * self.dimension..(pos + 1) * self.dimension])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// messagepack, same as [`crate::hnsw::HNSW::save`]
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut wtr = BufWriter::new(File::create(path)?);
+        rmp_serde::encode::write(&mut wtr, &(self.dimension, &self.ids, &self.data))?;
+        Ok(())
+    }
+
+    pub fn load(
+        path: &Path,
+        dist_calc: RefCell<Box<dyn DistanceCalculator>>,
+    ) -> Result<Self, Error> {
+        let rdr = BufReader::new(File::open(path)?);
+        let (dimension, ids, data): (usize, Vec<Uuid>, Vec<f32>) =
+            rmp_serde::decode::from_read(rdr)?;
+        if dimension == 0 || data.len() != ids.len() * dimension {
+            return Err(Error::EmbSizeError);
+        }
+        let positions = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        Ok(Self {
+            dimension,
+            dist_calc,
+            ids,
+            data,
+            positions,
+        })
+    }
+
+    pub fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        let pos = match self.positions.remove(&id) {
+            Some(p) => p,
+            None => return Err(Error::InvalidId),
+        };
+
+        // move the last vector into the hole
+        let last = self.ids.len() - 1;
+        self.ids.swap_remove(pos);
+        if pos != last {
+            let (front, back) = self.data.split_at_mut(last * self.dimension);
+            front[pos * self.dimension..(pos + 1) * self.dimension].copy_from_slice(back);
+            self.positions.insert(self.ids[pos], pos);
+        }
+        self.data.truncate(last * self.dimension);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// what a graph index looks like and anything wrong with it, see
+/// [`crate::hnsw::HNSW::stats`] and [`crate::tinyworld::TinyWorld::stats`]
+#[derive(Debug, Clone)]
+pub struct GraphStats<Id> {
+    // level 0 first
+    pub nodes_per_level: Vec<usize>,
+    // degrees[level][d] is how many nodes on that level have d neighbors
+    pub degrees: Vec<Vec<usize>>,
+    // what the degrees should stay under, m0_max on level 0 and m_max above
+    pub max_degree: Vec<usize>,
+    // a -> b without b -> a, per level. pruning leaves plenty of these so they
+    // aren't a problem on their own
+    pub asymmetric_edges: Vec<usize>,
+    pub issues: Vec<GraphIssue<Id>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphIssue<Id> {
+    // theres nodes but the entry point is missing, or not on the top level
+    BadEntry,
+    // no path to it from the entry point on level 0, so no search can find it
+    Unreachable(Id),
+    // a connection to something that isn't on that level (or anywhere)
+    Dangling { level: usize, from: Id, to: Id },
+    // more neighbors than the level allows
+    OverDegree { level: usize, id: Id, degree: usize },
+}
+
+impl<Id> GraphStats<Id> {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// goes over every level of a graph given as each node's neighbors, `entry` is
+/// the entry point and its level
+pub(crate) fn inspect<Id: Copy + Eq + Hash>(
+    levels: &[HashMap<Id, Vec<Id>>],
+    entry: Option<(Id, usize)>,
+    m0_max: usize,
+    m_max: usize,
+) -> GraphStats<Id> {
+    let mut stats = GraphStats {
+        nodes_per_level: vec![],
+        degrees: vec![],
+        max_degree: vec![],
+        asymmetric_edges: vec![],
+        issues: vec![],
+    };
+
+    let entry_ok = match entry {
+        Some((id, level)) => level + 1 == levels.len() && levels[level].contains_key(&id),
+        None => levels.iter().all(|l| l.is_empty()),
+    };
+    if !entry_ok {
+        stats.issues.push(GraphIssue::BadEntry);
+    }
+
+    for (level, nodes) in levels.iter().enumerate() {
+        let max_degree = match level {
+            0 => m0_max,
+            _ => m_max,
+        };
+        let mut degrees = vec![0; max_degree + 1];
+        let mut asymmetric = 0;
+        for (id, nbs) in nodes {
+            if degrees.len() <= nbs.len() {
+                degrees.resize(nbs.len() + 1, 0);
+            }
+            degrees[nbs.len()] += 1;
+            if nbs.len() > max_degree {
+                stats.issues.push(GraphIssue::OverDegree {
+                    level,
+                    id: *id,
+                    degree: nbs.len(),
+                });
+            }
+
+            for nb in nbs {
+                match nodes.get(nb) {
+                    Some(back) => {
+                        if !back.contains(id) {
+                            asymmetric += 1;
+                        }
+                    }
+                    None => stats.issues.push(GraphIssue::Dangling {
+                        level,
+                        from: *id,
+                        to: *nb,
+                    }),
+                }
+            }
+        }
+
+        stats.nodes_per_level.push(nodes.len());
+        stats.degrees.push(degrees);
+        stats.max_degree.push(max_degree);
+        stats.asymmetric_edges.push(asymmetric);
+    }
+
+    // walk level 0 from the entry point, anything left over is stranded
+    if let (Some((entry_id, _)), Some(level0)) = (entry, levels.first()) {
+        let mut seen = HashSet::new();
+        let mut stack = vec![entry_id];
+        while let Some(id) = stack.pop() {
+            if seen.insert(id) {
+                if let Some(nbs) = level0.get(&id) {
+                    stack.extend(nbs.iter().filter(|nb| !seen.contains(*nb)));
+                }
+            }
+        }
+        stats.issues.extend(
+            level0
+                .keys()
+                .filter(|id| !seen.contains(*id))
+                .map(|id| GraphIssue::Unreachable(*id)),
+        );
+    }
+
+    stats
+}
@@ -1,23 +1,54 @@
 use std::{
     cell::RefCell,
     cmp::Reverse,
-    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
-    usize,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    sync::Mutex,
+    thread,
 };
 
-use rand::{rngs::ThreadRng, Rng};
+use rand::{distributions::Open01, rngs::ThreadRng, Rng};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
     distance_calculators::DistanceCalculator,
+    graph_stats::{self, GraphIssue, GraphStats},
+    search::{Payload, SearchHit, SearchOptions, SearchStats, StopReason},
     utils::{MaxDist, MinDist},
 };
 
 #[derive(Debug)]
 pub enum Error {
     EmbSizeError,
+    InvalidId,
+    DuplicateId,
+    IoError(io::Error),
+    EncodeError(rmp_serde::encode::Error),
+    DecodeError(rmp_serde::decode::Error),
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Self::EncodeError(e)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        Self::DecodeError(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct FixedParams {
     pub dimension: u32,
     pub m: u32,
@@ -25,6 +56,18 @@ pub struct FixedParams {
     pub m0_max: u32,
     pub ef_construction: u32,
     pub level_norm: f32,
+    // use the diversity heuristic (algorithm 4 in the paper) to pick neighbors,
+    // otherwise its just the closest ones (algorithm 3)
+    pub neighbor_heuristic: bool,
+    // also consider the candidates' neighbors when picking neighbors
+    pub extend_candidates: bool,
+    // fill up with the closest of the candidates the heuristic skipped if it
+    // didn't find enough
+    pub keep_pruned_connections: bool,
+    // when pruning a full neighbor list drops a node that nothing nearby links
+    // back to, link it back in right away instead of waiting for
+    // `HNSW::reconnect_orphans`
+    pub repair_on_insert: bool,
     // dist_id: u8,
 }
 
@@ -37,328 +80,1123 @@ impl Default for FixedParams {
             m_max: 50,
             m0_max: 100,
             ef_construction: 50,
-            level_norm: 10.0,
+            // 1/ln(m), from the paper
+            level_norm: 1.0 / f32::ln(24.0),
+            neighbor_heuristic: true,
+            extend_candidates: false,
+            keep_pruned_connections: true,
+            repair_on_insert: false,
+        }
+    }
+}
+
+/// the highest level a node can get, no matter what `level_norm` is
+pub const MAX_LEVEL: u32 = 16;
+
+/// a level for a new node, `floor(-ln(U) * level_norm)` with U uniform on
+/// (0, 1) so there's never an ln(0). the chance of landing on level `l` or
+/// higher is `e^(-l / level_norm)`, so `1/ln(m)` gives each level about `1/m`
+/// of the nodes of the one below it
+pub(crate) fn random_level(rng: &mut impl Rng, level_norm: f32) -> u32 {
+    let u: f32 = rng.sample(Open01);
+    let level = f32::floor(-f32::ln(u) * level_norm);
+    // float to int casts saturate, so a nan or negative level_norm ends up at 0
+    u32::min(level as u32, MAX_LEVEL)
+}
+
+const REPAIR_ROUNDS: usize = 4;
+
+/// every node's neighbors, each level of a node gets a fixed size block of a
+/// count and then `m0_max` (on level 0) or `m_max` neighbor slots. level 0 is
+/// one flat array over all the nodes so a search step is a single contiguous
+/// read, the levels above it get an array per node since most nodes aren't on
+/// any of them
+#[derive(Serialize, Deserialize)]
+struct Adjacency {
+    m0_max: usize,
+    m_max: usize,
+    // how many levels each node is on, 0 if its been deleted
+    levels: Vec<u32>,
+    level0: Vec<u32>,
+    upper: Vec<Vec<u32>>,
+}
+
+impl Adjacency {
+    fn new(m0_max: usize, m_max: usize) -> Self {
+        Self {
+            m0_max,
+            m_max,
+            levels: vec![],
+            level0: vec![],
+            upper: vec![],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn levels(&self, node: u32) -> u32 {
+        self.levels[node as usize]
+    }
+
+    /// sets `node` up with no neighbors on levels 0 through `top`, `node` is
+    /// either a new one on the end or one thats been deleted
+    fn add_node(&mut self, node: u32, top: u32) {
+        let upper = vec![0; top as usize * (self.m_max + 1)];
+        match node as usize == self.len() {
+            true => {
+                self.levels.push(top + 1);
+                self.level0.resize(self.level0.len() + self.m0_max + 1, 0);
+                self.upper.push(upper);
+            }
+            false => {
+                self.levels[node as usize] = top + 1;
+                self.upper[node as usize] = upper;
+            }
+        }
+    }
+
+    fn remove_node(&mut self, node: u32) {
+        self.set_neighbors(node, 0, &[]);
+        self.levels[node as usize] = 0;
+        self.upper[node as usize] = vec![];
+    }
+
+    // where the block for `node` on `level` starts, and its number of slots
+    fn block(&self, node: u32, level: u32) -> (&[u32], usize) {
+        match level {
+            0 => (
+                &self.level0[node as usize * (self.m0_max + 1)..],
+                self.m0_max,
+            ),
+            _ => (
+                &self.upper[node as usize][(level as usize - 1) * (self.m_max + 1)..],
+                self.m_max,
+            ),
+        }
+    }
+
+    fn block_mut(&mut self, node: u32, level: u32) -> (&mut [u32], usize) {
+        match level {
+            0 => (
+                &mut self.level0[node as usize * (self.m0_max + 1)..],
+                self.m0_max,
+            ),
+            _ => (
+                &mut self.upper[node as usize][(level as usize - 1) * (self.m_max + 1)..],
+                self.m_max,
+            ),
+        }
+    }
+
+    /// how many neighbors a node can have on `level`
+    fn capacity(&self, level: u32) -> usize {
+        match level {
+            0 => self.m0_max,
+            _ => self.m_max,
+        }
+    }
+
+    fn neighbors(&self, node: u32, level: u32) -> &[u32] {
+        let (block, _) = self.block(node, level);
+        &block[1..1 + block[0] as usize]
+    }
+
+    fn set_neighbors(&mut self, node: u32, level: u32, neighbors: &[u32]) {
+        let (block, slots) = self.block_mut(node, level);
+        let count = usize::min(neighbors.len(), slots);
+        block[0] = count as u32;
+        block[1..1 + count].copy_from_slice(&neighbors[..count]);
+    }
+
+    /// false if theres no room left
+    fn push_neighbor(&mut self, node: u32, level: u32, neighbor: u32) -> bool {
+        let (block, slots) = self.block_mut(node, level);
+        let count = block[0] as usize;
+        match count < slots {
+            true => {
+                block[1 + count] = neighbor;
+                block[0] += 1;
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn remove_neighbor(&mut self, node: u32, level: u32, neighbor: u32) {
+        let (block, _) = self.block_mut(node, level);
+        let count = block[0] as usize;
+        if let Some(i) = block[1..1 + count].iter().position(|n| *n == neighbor) {
+            block.copy_within(2 + i..1 + count, 1 + i);
+            block[0] -= 1;
         }
     }
 }
 
 pub struct HNSW {
-    entry: Option<(Uuid, u32)>,
+    // (node, level)
+    entry: Option<(u32, u32)>,
     rng: ThreadRng,
     fixed_params: FixedParams,
     dist_calc: RefCell<Box<dyn DistanceCalculator>>,
-    embeddings: HashMap<Uuid, Vec<f32>>,
-    connections: Vec<HashMap<Uuid, Vec<(Uuid, f32)>>>,
+    // everything inside is keyed by dense node ids, node n's vector is
+    // `vectors[n * dimension..(n + 1) * dimension]`
+    vectors: Vec<f32>,
+    adjacency: Adjacency,
+    // uuids only come in at the api, these go back and forth. deleted node ids
+    // get reused
+    uuids: Vec<Uuid>,
+    node_ids: HashMap<Uuid, u32>,
+    free_node_ids: Vec<u32>,
+    payloads: HashMap<Uuid, Payload>,
+    contexts: Mutex<Vec<SearchContext>>,
 }
 
 enum Query<'q> {
-    Id(Uuid),
+    Id(u32),
     Data(&'q [f32]),
 }
 
+/// the heaps and visited set a search works with. these get pooled by the
+/// index and handed back out, so after warming up a search doesn't allocate
+/// anything until it builds its results
+#[derive(Default)]
+struct SearchContext {
+    candidates: BinaryHeap<MinDist<u32>>,
+    found: BinaryHeap<MaxDist<u32>>,
+    // indexed by node id, a node has been visited in this search if its entry
+    // is the current epoch. bumping the epoch clears the whole thing
+    visited: Vec<u32>,
+    epoch: u32,
+    // counted over a whole search
+    stats: SearchStats,
+}
+
+impl SearchContext {
+    fn start(&mut self, nodes: usize) {
+        self.candidates.clear();
+        self.found.clear();
+        if self.visited.len() < nodes {
+            self.visited.resize(nodes, 0);
+        }
+        self.epoch = self.epoch.wrapping_add(1);
+        // only after 4 billion searches, but stale entries would look visited
+        if self.epoch == 0 {
+            self.visited.fill(0);
+            self.epoch = 1;
+        }
+    }
+
+    /// true the first time `node` gets visited this search
+    fn visit(&mut self, node: u32) -> bool {
+        let seen = &mut self.visited[node as usize];
+        match *seen == self.epoch {
+            true => false,
+            false => {
+                *seen = self.epoch;
+                true
+            }
+        }
+    }
+}
+
+// just the parts of the index a search reads, plus a distance calculator and
+// context of its own. the index itself can't be shared between threads (the
+// rng and the RefCell), but this can, one per thread
+struct Searcher<'a> {
+    dimension: usize,
+    vectors: &'a [f32],
+    adjacency: &'a Adjacency,
+    uuids: &'a [Uuid],
+    dist_calc: &'a mut dyn DistanceCalculator,
+    ctx: &'a mut SearchContext,
+}
+
+impl Searcher<'_> {
+    fn search(
+        &mut self,
+        query: &[f32],
+        entry: (u32, u32),
+        top_k: usize,
+        ef: usize,
+    ) -> Vec<(Uuid, f32)> {
+        let (mut entry_id, entry_level) = entry;
+        let stats = &mut self.ctx.stats;
+        stats.dist_evals = 0;
+        stats.hops = 0;
+        stats.visited.clear();
+        stats.visited.resize(entry_level as usize + 1, 0);
+        stats.candidates_peak = 0;
+        for level in (1..=entry_level).rev() {
+            self.search_layer(query, entry_id, 1, level, None);
+            entry_id = self.ctx.found.peek().unwrap().id;
+        }
+
+        let ef = usize::max(ef, top_k);
+        self.search_layer(query, entry_id, ef, 0, None);
+        #[cfg(feature = "log")]
+        log::debug!(
+            "search: {} distance evals, {} hops, {} levels",
+            self.ctx.stats.dist_evals,
+            self.ctx.stats.hops,
+            entry_level + 1
+        );
+        let mut out = self.results();
+        out.sort_by(|a, b| a.1.total_cmp(&b.1));
+        out.truncate(top_k);
+        out.into_iter()
+            .map(|(n, dist)| (self.uuids[n as usize], dist))
+            .collect()
+    }
+
+    // leaves what it found in the context, see [`Searcher::results`]
+    fn search_layer(
+        &mut self,
+        query: &[f32],
+        entry: u32,
+        top_k: usize,
+        level: u32,
+        // nodes this says no to still get walked through, they just don't end
+        // up in the results
+        filter: Option<&dyn Fn(u32) -> bool>,
+    ) {
+        let accepts = |n: u32| filter.is_none_or(|f| f(n));
+        self.ctx.start(self.adjacency.len());
+        let ctx = &mut *self.ctx;
+        let (mut visited, mut stop_reason) = (1, StopReason::Exhausted);
+
+        {
+            let dist = self
+                .dist_calc
+                .calc_dist(query, vector(self.vectors, self.dimension, entry));
+            ctx.visit(entry);
+            ctx.stats.dist_evals += 1;
+            ctx.candidates
+                .push(MinDist(Reverse(MaxDist { id: entry, dist })));
+            ctx.stats.candidates_peak = usize::max(ctx.stats.candidates_peak, 1);
+            if accepts(entry) {
+                ctx.found.push(MaxDist { dist, id: entry });
+            }
+        }
+
+        while let Some(c) = ctx.candidates.pop() {
+            if let Some(f) = ctx.found.peek() {
+                if ctx.found.len() >= top_k && c.0 .0.dist > f.dist {
+                    stop_reason = StopReason::Converged;
+                    break;
+                }
+            }
+
+            let c_id = c.0 .0.id;
+            ctx.stats.hops += 1;
+            // shouldn't happen, but one node missing its connections doesn't
+            // mean the rest of the candidates are done
+            if self.adjacency.levels(c_id) <= level {
+                continue;
+            }
+
+            for nb in self.adjacency.neighbors(c_id, level) {
+                if ctx.visit(*nb) {
+                    let dist = self
+                        .dist_calc
+                        .calc_dist(query, vector(self.vectors, self.dimension, *nb));
+                    ctx.stats.dist_evals += 1;
+                    visited += 1;
+
+                    if ctx.found.len() < top_k || ctx.found.peek().is_some_and(|f| dist < f.dist) {
+                        ctx.candidates
+                            .push(MinDist(Reverse(MaxDist { dist, id: *nb })));
+                        ctx.stats.candidates_peak =
+                            usize::max(ctx.stats.candidates_peak, ctx.candidates.len());
+                        if accepts(*nb) {
+                            ctx.found.push(MaxDist { dist, id: *nb });
+                        }
+
+                        if ctx.found.len() > top_k {
+                            ctx.found.pop();
+                        }
+                    }
+                }
+            }
+        }
+
+        let stats = &mut ctx.stats;
+        if stats.visited.len() <= level as usize {
+            stats.visited.resize(level as usize + 1, 0);
+        }
+        stats.visited[level as usize] += visited;
+        stats.stop_reason = stop_reason;
+    }
+
+    fn results(&mut self) -> Vec<(u32, f32)> {
+        self.ctx.found.drain().map(|f| (f.id, f.dist)).collect()
+    }
+}
+
+fn vector(vectors: &[f32], dimension: usize, node: u32) -> &[f32] {
+    let start = node as usize * dimension;
+    &vectors[start..start + dimension]
+}
+
 impl HNSW {
     pub fn new(fixed_params: FixedParams, dist_calc: RefCell<Box<dyn DistanceCalculator>>) -> Self {
         let rng = rand::thread_rng();
+        let adjacency = Adjacency::new(fixed_params.m0_max as usize, fixed_params.m_max as usize);
         Self {
             entry: None,
             rng,
             fixed_params,
             dist_calc,
-            embeddings: HashMap::new(),
-            connections: vec![],
+            vectors: vec![],
+            adjacency,
+            uuids: vec![],
+            node_ids: HashMap::new(),
+            free_node_ids: vec![],
+            payloads: HashMap::new(),
+            contexts: Mutex::new(vec![]),
         }
     }
 
     // TODO: figure out best place for ef
-    pub fn search(&self, query: &[f32], top_k: usize, ef: usize) -> Vec<(Uuid, f32)> {
-        match self.entry {
-            Some((mut entry_id, entry_level)) => {
-                for level in (1..=entry_level).rev() {
-                    entry_id = self.search_layer(Query::Data(query), entry_id, 1, level)[0].0;
-                }
-
-                let mut out = self.search_layer(Query::Data(query), entry_id, ef, 0);
-                out.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-                out.get(0..top_k).unwrap().to_vec()
+    /// up to `top_k` results, closest first. there can be less than `top_k` if
+    /// the index is that small, `ef` gets bumped up to `top_k` if its less
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(Uuid, f32)>, Error> {
+        self.check_query(query)?;
+        Ok(match self.entry {
+            Some(entry) => {
+                let mut dist_calc = self.dist_calc.borrow_mut();
+                let mut ctx = self.take_context();
+                let out = self
+                    .searcher(dist_calc.as_mut(), &mut ctx)
+                    .search(query, entry, top_k, ef);
+                self.put_context(ctx);
+                out
             }
             None => vec![],
+        })
+    }
+
+    /// [`HNSW::search`], along with what the search did to get there
+    pub fn search_with_stats(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<(Vec<(Uuid, f32)>, SearchStats), Error> {
+        self.check_query(query)?;
+        Ok(match self.entry {
+            Some(entry) => {
+                let mut dist_calc = self.dist_calc.borrow_mut();
+                let mut ctx = self.take_context();
+                let out = self
+                    .searcher(dist_calc.as_mut(), &mut ctx)
+                    .search(query, entry, top_k, ef);
+                let stats = ctx.stats.clone();
+                self.put_context(ctx);
+                (out, stats)
+            }
+            None => (vec![], SearchStats::default()),
+        })
+    }
+
+    /// [`HNSW::search`] for each of `queries`, split up across threads. every
+    /// thread gets its own distance calculator (see
+    /// [`DistanceCalculator::new_instance`]) and reuses one pooled search context
+    /// for all of its queries. results come back in the same order
+    /// as `queries` and are the same as searching them one at a time. if any of
+    /// the queries is the wrong size none of them get searched
+    pub fn search_batch(
+        &self,
+        queries: &[&[f32]],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<Vec<(Uuid, f32)>>, Error> {
+        for query in queries {
+            self.check_query(query)?;
+        }
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return Ok(vec![vec![]; queries.len()]),
+        };
+
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .clamp(1, usize::max(queries.len(), 1));
+        let dist_calcs = match threads {
+            1 => None,
+            _ => (0..threads)
+                .map(|_| self.dist_calc.borrow().new_instance())
+                .collect::<Option<Vec<_>>>(),
+        };
+
+        Ok(match dist_calcs {
+            Some(dist_calcs) => {
+                let chunk_size = queries.len().div_ceil(threads);
+                let dimension = self.fixed_params.dimension as usize;
+                let (vectors, adjacency, uuids) = (&self.vectors, &self.adjacency, &self.uuids);
+                let contexts = &self.contexts;
+                thread::scope(|s| {
+                    let handles = queries
+                        .chunks(chunk_size)
+                        .zip(dist_calcs)
+                        .map(|(chunk, mut dist_calc)| {
+                            s.spawn(move || {
+                                let mut ctx = contexts.lock().unwrap().pop().unwrap_or_default();
+                                let mut searcher = Searcher {
+                                    dimension,
+                                    vectors,
+                                    adjacency,
+                                    uuids,
+                                    dist_calc: dist_calc.as_mut(),
+                                    ctx: &mut ctx,
+                                };
+                                let out = chunk
+                                    .iter()
+                                    .map(|q| searcher.search(q, entry, top_k, ef))
+                                    .collect::<Vec<_>>();
+                                contexts.lock().unwrap().push(ctx);
+                                out
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    handles
+                        .into_iter()
+                        .flat_map(|h| h.join().unwrap())
+                        .collect()
+                })
+            }
+            // one thread, or a calculator that can't be copied
+            None => {
+                let mut dist_calc = self.dist_calc.borrow_mut();
+                let mut ctx = self.take_context();
+                let mut searcher = self.searcher(dist_calc.as_mut(), &mut ctx);
+                let out = queries
+                    .iter()
+                    .map(|q| searcher.search(q, entry, top_k, ef))
+                    .collect();
+                self.put_context(ctx);
+                out
+            }
+        })
+    }
+
+    /// every vector within `radius` of `query`, closest first. this starts out
+    /// like a search with `ef` and keeps doubling `ef` for as long as everything
+    /// it found is still inside the radius, since then there could be more
+    pub fn range_search(
+        &self,
+        query: &[f32],
+        radius: f32,
+        ef: usize,
+    ) -> Result<Vec<(Uuid, f32)>, Error> {
+        self.check_query(query)?;
+        let (mut entry_id, entry_level) = match self.entry {
+            Some(entry) => entry,
+            None => return Ok(vec![]),
+        };
+        for level in (1..=entry_level).rev() {
+            entry_id = self.search_layer(Query::Data(query), entry_id, 1, level, None)[0].0;
         }
+
+        let mut ef = usize::max(ef, 1);
+        loop {
+            let found = self.search_layer(Query::Data(query), entry_id, ef, 0, None);
+            let exhausted = found.len() < ef || ef >= self.len();
+            if exhausted || found.iter().any(|f| f.1 > radius) {
+                let mut out = found
+                    .into_iter()
+                    .filter(|f| f.1 <= radius)
+                    .map(|(n, dist)| (self.uuids[n as usize], dist))
+                    .collect::<Vec<_>>();
+                out.sort_by(|a, b| a.1.total_cmp(&b.1));
+                return Ok(out);
+            }
+            ef *= 2;
+        }
+    }
+
+    /// [`HNSW::search`], but with filters and the hits filled in with whatever
+    /// `options` asks for
+    pub fn search_with(
+        &self,
+        query: &[f32],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchHit>, Error> {
+        self.check_query(query)?;
+        let (mut entry_id, entry_level) = match self.entry {
+            Some(entry) => entry,
+            None => return Ok(vec![]),
+        };
+        for level in (1..=entry_level).rev() {
+            entry_id = self.search_layer(Query::Data(query), entry_id, 1, level, None)[0].0;
+        }
+
+        let accepts = |n: u32| {
+            let id = &self.uuids[n as usize];
+            options.accepts(id, self.payloads.get(id))
+        };
+        let filter: Option<&dyn Fn(u32) -> bool> = match options.filters.is_empty() {
+            true => None,
+            false => Some(&accepts),
+        };
+        let ef = usize::max(options.ef, options.top_k);
+        let mut out = self.search_layer(Query::Data(query), entry_id, ef, 0, filter);
+        out.sort_by(|a, b| a.1.total_cmp(&b.1));
+        out.truncate(options.top_k);
+
+        let dist_calc = self.dist_calc.borrow();
+        Ok(out
+            .into_iter()
+            .map(|(n, distance)| {
+                let id = self.uuids[n as usize];
+                SearchHit {
+                    id,
+                    distance,
+                    score: dist_calc.score(distance),
+                    vector: match options.include_vectors {
+                        true => Some(self.vector(n).to_vec()),
+                        false => None,
+                    },
+                    payload: match options.include_payloads {
+                        true => self.payloads.get(&id).cloned(),
+                        false => None,
+                    },
+                }
+            })
+            .collect())
     }
 
     pub fn insert(&mut self, new_data: &[f32]) -> Result<Uuid, Error> {
+        let id = Uuid::new_v4();
+        self.insert_with_id(id, new_data)?;
+        Ok(id)
+    }
+
+    /// [`HNSW::insert`] under an id from somewhere else, like an import, instead
+    /// of a new random one
+    pub fn insert_with_id(&mut self, new_emb_id: Uuid, new_data: &[f32]) -> Result<(), Error> {
         if new_data.len() != self.fixed_params.dimension as usize {
             return Err(Error::EmbSizeError);
         }
+        if self.node_ids.contains_key(&new_emb_id) {
+            return Err(Error::DuplicateId);
+        }
 
-        let new_level =
-            f32::floor(-f32::ln(self.rng.gen_range(0.0..=1.0)) * self.fixed_params.level_norm)
-                as u32;
-        println!("new level: {new_level}");
-        let new_emb_id = Uuid::new_v4();
-        let new_emb_data = Vec::from(new_data);
-        self.embeddings.insert(new_emb_id, new_emb_data);
-
-        match &mut self.entry {
+        let new_level = random_level(&mut self.rng, self.fixed_params.level_norm);
+        #[cfg(feature = "log")]
+        log::trace!("insert: new node at level {new_level}");
+        let node = match self.free_node_ids.pop() {
+            Some(node) => {
+                let start = node as usize * new_data.len();
+                self.vectors[start..start + new_data.len()].copy_from_slice(new_data);
+                self.uuids[node as usize] = new_emb_id;
+                node
+            }
             None => {
-                self.entry = Some((new_emb_id, new_level));
-                for _ in 0..=new_level {
-                    self.connections
-                        .push(HashMap::from_iter([(new_emb_id, vec![])]));
-                }
+                self.vectors.extend_from_slice(new_data);
+                self.uuids.push(new_emb_id);
+                self.uuids.len() as u32 - 1
             }
-            Some(ep) => {
-                if new_level > ep.1 {
-                    for _ in ep.1 as usize..=new_level as usize {
-                        self.connections
-                            .push(HashMap::from_iter([(new_emb_id, vec![])]));
-                    }
-                    *ep = (new_emb_id, new_level);
-                }
+        };
+        self.node_ids.insert(new_emb_id, node);
+        self.adjacency.add_node(node, new_level);
 
-                let entry_point = self.entry.unwrap().clone();
+        match self.entry {
+            None => self.entry = Some((node, new_level)),
+            Some(entry_point) => {
                 let mut entry_point_id = entry_point.0;
 
                 // find entry for new level
                 for level in (new_level + 1..=entry_point.1).rev() {
                     entry_point_id =
-                        self.search_layer(Query::Id(new_emb_id), entry_point_id, 1, level)[0].0;
+                        self.search_layer(Query::Id(node), entry_point_id, 1, level, None)[0].0;
                 }
 
                 // insert node at each level for the rest of the way down
-                for level in (0..=new_level).rev() {
+                for level in (0..=u32::min(new_level, entry_point.1)).rev() {
                     let top_ef_construction = self.search_layer(
-                        Query::Id(new_emb_id),
+                        Query::Id(node),
                         entry_point_id,
                         self.fixed_params.ef_construction as usize,
                         level,
+                        None,
                     );
+                    // the next level down starts from the closest one found here
+                    let closest = top_ef_construction
+                        .iter()
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .unwrap()
+                        .0;
+                    let m_max = self.adjacency.capacity(level);
                     let selected_neighbors = self.select_neighbors(
-                        new_emb_id,
+                        node,
                         top_ef_construction,
-                        self.fixed_params.m as usize,
+                        usize::min(self.fixed_params.m as usize, m_max),
                         level,
-                        // TODO:
-                        None,
-                        None,
                     );
+                    let selected_ids = selected_neighbors.iter().map(|n| n.0).collect::<Vec<_>>();
+                    self.adjacency.set_neighbors(node, level, &selected_ids);
 
-                    {
-                        self.connections
-                            .get_mut(level as usize)
-                            .unwrap()
-                            .insert(new_emb_id, selected_neighbors.clone());
-                    }
+                    for neighbor in selected_ids {
+                        if !self.adjacency.push_neighbor(neighbor, level, node) {
+                            // full, so the new node has to compete with the
+                            // ones already there
+                            let n_conns = self.with_dists(neighbor, level, node);
+                            let selected = self
+                                .select_neighbors(neighbor, n_conns.clone(), m_max, level)
+                                .into_iter()
+                                .map(|n| n.0)
+                                .collect::<Vec<_>>();
+                            self.adjacency.set_neighbors(neighbor, level, &selected);
 
-                    let m_max = match level {
-                        0 => self.fixed_params.m0_max as usize,
-                        _ => self.fixed_params.m_max as usize,
-                    };
-                    for neighbor in selected_neighbors {
-                        {
-                            self.connections
-                                .get_mut(level as usize)
-                                .unwrap()
-                                .get_mut(&neighbor.0)
-                                .unwrap()
-                                .push((new_emb_id, neighbor.1));
-                        }
-                        let update = {
-                            let n_conns = self
-                                .connections
-                                .get(level as usize)
-                                .unwrap()
-                                .get(&neighbor.0)
-                                .unwrap();
-                            match n_conns.len() > m_max {
-                                true => {
-                                    let selected = self.select_neighbors(
-                                        neighbor.0,
-                                        n_conns.clone(),
-                                        m_max,
-                                        level,
-                                        // TODO:
-                                        None,
-                                        None,
-                                    );
-                                    Some(selected)
+                            if self.fixed_params.repair_on_insert {
+                                for (dropped, _) in n_conns {
+                                    if !selected.contains(&dropped)
+                                        && self.looks_orphaned(dropped, level)
+                                    {
+                                        self.link_back(dropped, level);
+                                    }
                                 }
-                                false => None,
                             }
-                        };
-
-                        if let Some(new) = update {
-                            self.connections
-                                .get_mut(level as usize)
-                                .unwrap()
-                                .insert(neighbor.0, new);
                         }
                     }
+
+                    entry_point_id = closest;
                 }
 
-                if new_level > self.entry.unwrap().1 {
-                    self.entry = Some((new_emb_id, new_level));
+                // the new node only becomes the entry point once its linked in
+                if new_level > entry_point.1 {
+                    self.entry = Some((node, new_level));
                 }
             }
         }
 
-        Ok(new_emb_id)
+        Ok(())
+    }
+
+    /// [`HNSW::insert`] with a payload that comes back with search hits
+    pub fn insert_with_payload(
+        &mut self,
+        new_data: &[f32],
+        payload: Payload,
+    ) -> Result<Uuid, Error> {
+        let id = self.insert(new_data)?;
+        self.payloads.insert(id, payload);
+        Ok(id)
+    }
+
+    pub fn payload(&self, id: Uuid) -> Option<&Payload> {
+        self.payloads.get(&id)
+    }
+
+    pub fn set_payload(&mut self, id: Uuid, payload: Payload) -> Result<(), Error> {
+        if !self.node_ids.contains_key(&id) {
+            return Err(Error::InvalidId);
+        }
+        self.payloads.insert(id, payload);
+        Ok(())
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<&[f32]> {
+        self.node_ids.get(&id).map(|n| self.vector(*n))
+    }
+
+    /// finds nodes that can't be reached from the entry point on some level
+    /// (pruning full neighbor lists can drop the only links to a node) and links
+    /// them back in from the closest nodes that can be. returns how many times a
+    /// node needed it
+    pub fn reconnect_orphans(&mut self) -> usize {
+        let top = match self.entry {
+            Some((_, top)) => top,
+            None => return 0,
+        };
+
+        let mut reconnected = 0;
+        for level in (0..=top).rev() {
+            // linking one node back in can push another out of a full neighbor
+            // list, so go around a few times
+            for _ in 0..REPAIR_ROUNDS {
+                let orphans = self.unreachable(level);
+                if orphans.is_empty() {
+                    break;
+                }
+                reconnected += orphans.len();
+                for orphan in orphans {
+                    self.link_back(orphan, level);
+                }
+            }
+        }
+        reconnected
+    }
+
+    /// node counts, degrees and anything wrong with the graph
+    pub fn stats(&self) -> GraphStats<Uuid> {
+        // links to ids past the end show up as the nil uuid
+        let uuid = |n: u32| self.uuids.get(n as usize).copied().unwrap_or_default();
+        let levels = (0..self.entry.map_or(0, |(_, top)| top + 1))
+            .map(|level| {
+                (0..self.adjacency.len() as u32)
+                    .filter(|n| self.adjacency.levels(*n) > level)
+                    .map(|n| {
+                        let nbs = self.adjacency.neighbors(n, level);
+                        (uuid(n), nbs.iter().map(|nb| uuid(*nb)).collect())
+                    })
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        graph_stats::inspect(
+            &levels,
+            self.entry.map(|(n, level)| (uuid(n), level as usize)),
+            self.fixed_params.m0_max as usize,
+            self.fixed_params.m_max as usize,
+        )
+    }
+
+    /// all the problems [`HNSW::stats`] finds, if there are any
+    pub fn check(&self) -> Result<(), Vec<GraphIssue<Uuid>>> {
+        let stats = self.stats();
+        match stats.is_healthy() {
+            true => Ok(()),
+            false => Err(stats.issues),
+        }
+    }
+
+    pub fn fixed_params(&self) -> &FixedParams {
+        &self.fixed_params
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.node_ids.is_empty()
+    }
+
+    /// writes the whole graph out with messagepack, see [`HNSW::load`]
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut wtr = BufWriter::new(File::create(path)?);
+        rmp_serde::encode::write(
+            &mut wtr,
+            &(
+                &self.entry,
+                &self.fixed_params,
+                &self.uuids,
+                &self.vectors,
+                &self.adjacency,
+                &self.payloads,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// the distance calculator isn't saved, so it has to be the same one the
+    /// index was built with
+    pub fn load(
+        path: &Path,
+        dist_calc: RefCell<Box<dyn DistanceCalculator>>,
+    ) -> Result<Self, Error> {
+        let rdr = BufReader::new(File::open(path)?);
+        let (entry, fixed_params, uuids, vectors, adjacency, payloads): (
+            _,
+            _,
+            Vec<Uuid>,
+            _,
+            Adjacency,
+            _,
+        ) = rmp_serde::decode::from_read(rdr)?;
+
+        let (mut node_ids, mut free_node_ids) = (HashMap::new(), vec![]);
+        for (n, id) in uuids.iter().enumerate() {
+            match adjacency.levels(n as u32) == 0 {
+                true => free_node_ids.push(n as u32),
+                false => {
+                    node_ids.insert(*id, n as u32);
+                }
+            }
+        }
+
+        Ok(Self {
+            entry,
+            rng: rand::thread_rng(),
+            fixed_params,
+            dist_calc,
+            vectors,
+            adjacency,
+            uuids,
+            node_ids,
+            free_node_ids,
+            payloads,
+            contexts: Mutex::new(vec![]),
+        })
+    }
+
+    /// takes a vector out of the graph, along with every connection to it. if it
+    /// was the entry point some other node on the top level takes over
+    pub fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        let node = match self.node_ids.remove(&id) {
+            Some(node) => node,
+            None => return Err(Error::InvalidId),
+        };
+        self.payloads.remove(&id);
+        let levels = self.adjacency.levels(node);
+        self.adjacency.remove_node(node);
+        self.free_node_ids.push(node);
+
+        // connections aren't always symmetric after pruning, so we have to look
+        // at everyone
+        for other in 0..self.adjacency.len() as u32 {
+            for level in 0..u32::min(levels, self.adjacency.levels(other)) {
+                self.adjacency.remove_neighbor(other, level, node);
+            }
+        }
+
+        if self.entry.is_some_and(|(ep, _)| ep == node) {
+            self.entry = (0..self.adjacency.len() as u32)
+                .filter(|n| self.adjacency.levels(*n) > 0)
+                .max_by_key(|n| self.adjacency.levels(*n))
+                .map(|n| (n, self.adjacency.levels(n) - 1));
+        }
+
+        Ok(())
+    }
+
+    // the kernels just zip, so a query of the wrong size would quietly get
+    // compared on however much of it overlaps
+    fn check_query(&self, query: &[f32]) -> Result<(), Error> {
+        match query.len() == self.fixed_params.dimension as usize {
+            true => Ok(()),
+            false => Err(Error::EmbSizeError),
+        }
     }
 
     fn search_layer(
         &self,
         query: Query,
-        entry: Uuid,
+        entry: u32,
         top_k: usize,
         level: u32,
-    ) -> Vec<(Uuid, f32)> {
-        println!("started search layer");
-        let mut candidates = BinaryHeap::<MinDist>::new();
-        let mut found = BinaryHeap::<MaxDist>::new();
-        let mut visited = HashSet::new();
-
-        let mut dist_calculator = self.dist_calc.borrow_mut();
-
+        filter: Option<&dyn Fn(u32) -> bool>,
+    ) -> Vec<(u32, f32)> {
         let query_data = match query {
-            Query::Id(id) => self.embeddings.get(&id).unwrap(),
+            Query::Id(n) => self.vector(n),
             Query::Data(data) => data,
         };
+        let mut dist_calc = self.dist_calc.borrow_mut();
+        let mut ctx = self.take_context();
+        let mut searcher = self.searcher(dist_calc.as_mut(), &mut ctx);
+        searcher.search_layer(query_data, entry, top_k, level, filter);
+        let out = searcher.results();
+        self.put_context(ctx);
+        out
+    }
 
-        {
-            let entry_data = self.embeddings.get(&entry).unwrap();
-            let dist = dist_calculator.calc_dist(&query_data, &entry_data);
-            visited.insert(entry);
-            candidates.push(MinDist(Reverse(MaxDist { id: entry, dist })));
-            found.push(MaxDist { dist, id: entry });
+    fn searcher<'a>(
+        &'a self,
+        dist_calc: &'a mut dyn DistanceCalculator,
+        ctx: &'a mut SearchContext,
+    ) -> Searcher<'a> {
+        Searcher {
+            dimension: self.fixed_params.dimension as usize,
+            vectors: &self.vectors,
+            adjacency: &self.adjacency,
+            uuids: &self.uuids,
+            dist_calc,
+            ctx,
         }
+    }
 
-        while candidates.len() > 0 {
-            let c = candidates.pop().unwrap();
-            let f = found.peek().unwrap();
+    fn vector(&self, node: u32) -> &[f32] {
+        vector(&self.vectors, self.fixed_params.dimension as usize, node)
+    }
 
-            if c.0 .0.dist > f.dist {
-                break;
+    // nodes on `level` that a walk from the entry point never gets to
+    fn unreachable(&self, level: u32) -> Vec<u32> {
+        let entry = match self.entry {
+            Some((entry, _)) => entry,
+            None => return vec![],
+        };
+        let mut seen = vec![false; self.adjacency.len()];
+        let mut stack = vec![entry];
+        while let Some(n) = stack.pop() {
+            if !seen[n as usize] {
+                seen[n as usize] = true;
+                stack.extend(self.adjacency.neighbors(n, level));
             }
+        }
+        (0..self.adjacency.len() as u32)
+            .filter(|n| self.adjacency.levels(*n) > level && !seen[*n as usize])
+            .collect()
+    }
 
-            let c_nbs = match self
-                .connections
-                .get(level as usize)
-                .unwrap()
-                .get(&c.0 .0.id)
-            {
-                Some(s) => s,
-                None => break,
-            };
-
-            for nb in c_nbs {
-                if !visited.contains(&nb.0) {
-                    let dist = {
-                        let nb_data = self.embeddings.get(&nb.0).unwrap();
-                        dist_calculator.calc_dist(&query_data, &nb_data)
-                    };
-                    visited.insert(nb.0);
-
-                    let f = found.peek().unwrap();
-
-                    if &dist < &f.dist || found.len() < top_k {
-                        candidates.push(MinDist(Reverse(MaxDist { dist, id: nb.0 })));
-                        found.push(MaxDist { dist, id: nb.0 });
-
-                        if found.len() > top_k {
-                            found.pop();
-                        }
+    // none of the nodes `node` links to link back to it. it could still have
+    // links in from further away, but this is cheap and usually right
+    fn looks_orphaned(&self, node: u32, level: u32) -> bool {
+        !self
+            .adjacency
+            .neighbors(node, level)
+            .iter()
+            .any(|nb| self.adjacency.neighbors(*nb, level).contains(&node))
+    }
+
+    // one of the nodes `node` links to, other than `except`, links back to it
+    fn linked_from_elsewhere(&self, node: u32, except: u32, level: u32) -> bool {
+        self.adjacency
+            .neighbors(node, level)
+            .iter()
+            .any(|nb| *nb != except && self.adjacency.neighbors(*nb, level).contains(&node))
+    }
+
+    // gives `node` links in from the closest nodes on `level` that the entry
+    // point can already get to
+    fn link_back(&mut self, node: u32, level: u32) {
+        let entry = match self.entry {
+            Some((entry, _)) if entry != node => entry,
+            _ => return,
+        };
+
+        // straight from the entry point, going down through the upper levels
+        // could land in the same cut off part of the graph as `node`
+        let not_node = |n: u32| n != node;
+        let found = self.search_layer(
+            Query::Id(node),
+            entry,
+            self.fixed_params.ef_construction as usize,
+            level,
+            Some(&not_node),
+        );
+        let m_max = self.adjacency.capacity(level);
+        let selected = self
+            .select_neighbors(
+                node,
+                found,
+                usize::min(self.fixed_params.m as usize, m_max),
+                level,
+            )
+            .into_iter()
+            .map(|n| n.0)
+            .collect::<Vec<_>>();
+
+        // pruning full lists here could cut off some other node, so only lists
+        // with room take it
+        let mut linked = false;
+        for nb in &selected {
+            linked |= self.adjacency.neighbors(*nb, level).contains(&node)
+                || self.adjacency.push_neighbor(*nb, level, node);
+        }
+
+        // everything was full, so it takes the place of the furthest neighbor
+        // (of the closest node it can) that has another way in
+        if !linked {
+            'outer: for nb in &selected {
+                let mut n_conns = self.with_dists(*nb, level, node);
+                // the last one is `node` itself
+                n_conns.pop();
+                n_conns.sort_by(|a, b| b.1.total_cmp(&a.1));
+                for (victim, _) in n_conns {
+                    if self.linked_from_elsewhere(victim, *nb, level) {
+                        let mut kept = self.adjacency.neighbors(*nb, level).to_vec();
+                        kept.retain(|n| *n != victim);
+                        kept.push(node);
+                        self.adjacency.set_neighbors(*nb, level, &kept);
+                        break 'outer;
                     }
                 }
             }
         }
 
-        found.into_iter().map(|f| (f.id, f.dist)).collect()
+        // and a way back out, if it lost all of its own
+        if self.adjacency.neighbors(node, level).is_empty() {
+            self.adjacency.set_neighbors(node, level, &selected);
+        }
+    }
+
+    // `node`'s neighbors on `level` plus `extra`, with their distances to `node`
+    fn with_dists(&self, node: u32, level: u32, extra: u32) -> Vec<(u32, f32)> {
+        let mut dist_calc = self.dist_calc.borrow_mut();
+        let node_data = self.vector(node);
+        self.adjacency
+            .neighbors(node, level)
+            .iter()
+            .chain([&extra])
+            .map(|n| (*n, dist_calc.calc_dist(node_data, self.vector(*n))))
+            .collect()
+    }
+
+    fn take_context(&self) -> SearchContext {
+        self.contexts.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    fn put_context(&self, ctx: SearchContext) {
+        self.contexts.lock().unwrap().push(ctx);
     }
 
+    /// picks up to `top_k` neighbors for `query` out of `candidates`. with the
+    /// heuristic a candidate only gets picked if it's closer to `query` than to
+    /// every neighbor picked so far, which keeps links going out in different
+    /// directions instead of all into the same cluster
     fn select_neighbors(
         &self,
-        query: Uuid,
-        candidates: Vec<(Uuid, f32)>,
+        query: u32,
+        candidates: Vec<(u32, f32)>,
         top_k: usize,
         level: u32,
-        keep_pruned: Option<bool>,
-        extend_cand: Option<bool>,
-    ) -> Vec<(Uuid, f32)> {
-        println!("started select neighbors");
-        let ext = match extend_cand {
-            Some(b) => b,
-            None => false,
-        };
-        let kp = match keep_pruned {
-            Some(b) => b,
-            None => true,
-        };
+    ) -> Vec<(u32, f32)> {
+        let mut cands = candidates;
+        if !self.fixed_params.neighbor_heuristic {
+            cands.sort_by(|a, b| a.1.total_cmp(&b.1));
+            cands.truncate(top_k);
+            return cands;
+        }
 
-        let mut cand_queue: HashMap<Uuid, f32> = HashMap::from_iter(candidates.clone().into_iter());
-        let mut cand_discard: HashMap<Uuid, f32> = HashMap::new();
-        let mut out: HashMap<Uuid, f32> = HashMap::new();
-
-        if ext {
-            for c in candidates {
-                let c_nbs = self
-                    .connections
-                    .get(level as usize)
-                    .unwrap()
-                    .get(&c.0)
-                    .unwrap();
-                for c_nb in c_nbs {
-                    if !cand_queue.contains_key(&c_nb.0) {
-                        let dist = {
-                            let mut dist_calc = self.dist_calc.borrow_mut();
-                            let query_data = self.embeddings.get(&query).unwrap();
-                            let c_nb_data = self.embeddings.get(&c_nb.0).unwrap();
-                            dist_calc.calc_dist(&query_data, &c_nb_data)
-                        };
-                        cand_queue.insert(c_nb.0, dist);
+        let mut dist_calc = self.dist_calc.borrow_mut();
+        let query_data = self.vector(query);
+
+        if self.fixed_params.extend_candidates {
+            let mut seen: HashSet<u32> = cands.iter().map(|c| c.0).collect();
+            seen.insert(query);
+            for i in 0..cands.len() {
+                if self.adjacency.levels(cands[i].0) <= level {
+                    continue;
+                }
+                for c_nb in self.adjacency.neighbors(cands[i].0, level) {
+                    if seen.insert(*c_nb) {
+                        cands.push((*c_nb, dist_calc.calc_dist(query_data, self.vector(*c_nb))));
                     }
                 }
             }
         }
 
-        while cand_queue.len() > 0 && out.len() < top_k {
-            let nearest_cand_id = {
-                cand_queue
-                    .iter()
-                    .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                    .unwrap()
-                    .0
-                    .clone()
-            };
-            let nearest_cand = cand_queue.remove_entry(&nearest_cand_id).unwrap();
-            if out.is_empty()
-                || &nearest_cand.1
-                    < out
-                        .iter()
-                        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                        .unwrap()
-                        .1
-            {
-                out.insert(nearest_cand.0, nearest_cand.1);
-            } else {
-                cand_discard.insert(nearest_cand.0, nearest_cand.1);
+        cands.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut out: Vec<(u32, f32)> = Vec::with_capacity(top_k);
+        let mut discarded = vec![];
+        for cand in cands {
+            if out.len() >= top_k {
+                break;
+            }
+            let cand_data = self.vector(cand.0);
+            let diverse = out
+                .iter()
+                .all(|o| cand.1 < dist_calc.calc_dist(cand_data, self.vector(o.0)));
+            match diverse {
+                true => out.push(cand),
+                false => discarded.push(cand),
             }
         }
 
-        if kp {
-            while cand_discard.len() > 0 && out.len() < top_k {
-                let nearest_discard_id = {
-                    cand_discard
-                        .iter()
-                        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                        .unwrap()
-                        .0
-                        .clone()
-                };
-
-                let nearest_discard = cand_discard.remove_entry(&nearest_discard_id).unwrap();
-                out.insert(nearest_discard.0, nearest_discard.1);
-            }
+        if self.fixed_params.keep_pruned_connections {
+            // these are already closest first
+            let room = top_k - out.len();
+            out.extend(discarded.into_iter().take(room));
         }
 
-        out.into_iter().collect()
+        out
     }
 }
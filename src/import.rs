@@ -0,0 +1,303 @@
+// bulk loading vectors (and their ids and payloads) out of parquet files or
+// arrow record batches
+
+use std::{collections::HashSet, fs::File, io, path::Path};
+
+use arrow::{
+    array::{Array, AsArray, RecordBatch},
+    datatypes::{DataType, Float32Type, Int64Type, UInt64Type},
+    error::ArrowError,
+    json::ArrayWriter,
+};
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ProjectionMask},
+    errors::ParquetError,
+};
+use uuid::Uuid;
+
+use crate::{
+    hnsw::{self, HNSW},
+    search::Payload,
+};
+
+#[derive(Debug)]
+pub enum ImportError {
+    IoError(io::Error),
+    ParquetError(ParquetError),
+    ArrowError(ArrowError),
+    JsonError(serde_json::Error),
+    IndexError(hnsw::Error),
+    // missing, the wrong type or has nulls
+    ColumnError(String),
+    DimensionError { expected: usize, found: usize },
+    // already in the index, or in the batch twice
+    DuplicateId(Uuid),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<ParquetError> for ImportError {
+    fn from(e: ParquetError) -> Self {
+        Self::ParquetError(e)
+    }
+}
+
+impl From<ArrowError> for ImportError {
+    fn from(e: ArrowError) -> Self {
+        Self::ArrowError(e)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::JsonError(e)
+    }
+}
+
+impl From<hnsw::Error> for ImportError {
+    fn from(e: hnsw::Error) -> Self {
+        Self::IndexError(e)
+    }
+}
+
+/// which columns go where, built up like
+/// `Importer::new("emb").id_column("id").payload_column("title")`
+pub struct Importer<'c> {
+    vector_column: &'c str,
+    id_column: Option<&'c str>,
+    payload_columns: Vec<&'c str>,
+    batch_size: usize,
+}
+
+impl<'c> Importer<'c> {
+    /// `vector_column` is a fixed size list (or list) of f32
+    pub fn new(vector_column: &'c str) -> Self {
+        Self {
+            vector_column,
+            id_column: None,
+            payload_columns: vec![],
+            batch_size: 1024,
+        }
+    }
+
+    /// where the ids come from, otherwise every row gets a new random one. it
+    /// can be strings holding uuids, 16 byte fixed size binary, or 64 bit
+    /// integers (which just become the low bits of the uuid)
+    pub fn id_column(mut self, id_column: &'c str) -> Self {
+        self.id_column = Some(id_column);
+        self
+    }
+
+    /// a column to put in each row's payload, the payload is a json object with
+    /// every one of these columns as a key
+    pub fn payload_column(mut self, payload_column: &'c str) -> Self {
+        self.payload_columns.push(payload_column);
+        self
+    }
+
+    /// rows read from a parquet file at a time
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// streams the file into `index` a batch at a time, only reading the columns
+    /// it needs. returns the ids in row order
+    pub fn import_parquet(&self, path: &Path, index: &mut HNSW) -> Result<Vec<Uuid>, ImportError> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+        let schema = builder.schema().clone();
+        let mut roots = vec![];
+        for name in self.columns() {
+            match schema.index_of(name) {
+                Ok(i) => roots.push(i),
+                Err(_) => return Err(ImportError::ColumnError(name.to_string())),
+            }
+        }
+        let mask = ProjectionMask::roots(builder.parquet_schema(), roots);
+        let reader = builder
+            .with_projection(mask)
+            .with_batch_size(self.batch_size)
+            .build()?;
+        self.import_batches(reader, index)
+    }
+
+    /// [`Importer::import_parquet`] but for batches from anywhere, like an arrow
+    /// ipc stream or a query engine
+    pub fn import_batches(
+        &self,
+        batches: impl IntoIterator<Item = Result<RecordBatch, ArrowError>>,
+        index: &mut HNSW,
+    ) -> Result<Vec<Uuid>, ImportError> {
+        let mut ids = vec![];
+        for batch in batches {
+            self.import_batch(&batch?, index, &mut ids)?;
+        }
+        Ok(ids)
+    }
+
+    fn import_batch(
+        &self,
+        batch: &RecordBatch,
+        index: &mut HNSW,
+        ids: &mut Vec<Uuid>,
+    ) -> Result<(), ImportError> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+
+        let dimension = index.fixed_params().dimension as usize;
+        let vectors = match self.column(batch, self.vector_column).and_then(vector_rows) {
+            Some(vectors) => vectors,
+            None => return Err(ImportError::ColumnError(self.vector_column.to_string())),
+        };
+        // everything gets checked up front so a bad row doesn't leave half a
+        // batch in the index
+        if let Some(v) = vectors.iter().find(|v| v.len() != dimension) {
+            return Err(ImportError::DimensionError {
+                expected: dimension,
+                found: v.len(),
+            });
+        }
+
+        let batch_ids = match self.id_column {
+            Some(name) => match self.column(batch, name).and_then(id_rows) {
+                Some(batch_ids) => batch_ids,
+                None => return Err(ImportError::ColumnError(name.to_string())),
+            },
+            None => (0..batch.num_rows()).map(|_| Uuid::new_v4()).collect(),
+        };
+        let mut seen = HashSet::with_capacity(batch_ids.len());
+        if let Some(id) = batch_ids
+            .iter()
+            .find(|id| index.get(**id).is_some() || !seen.insert(**id))
+        {
+            return Err(ImportError::DuplicateId(*id));
+        }
+        let payloads = match self.payload_columns.is_empty() {
+            true => None,
+            false => Some(self.payload_rows(batch)?),
+        };
+
+        for (i, (id, vector)) in batch_ids.iter().zip(vectors).enumerate() {
+            index.insert_with_id(*id, vector)?;
+            if let Some(payloads) = &payloads {
+                index.set_payload(*id, payloads[i].clone())?;
+            }
+        }
+        ids.extend(batch_ids);
+
+        Ok(())
+    }
+
+    fn columns(&self) -> impl Iterator<Item = &'c str> + '_ {
+        [self.vector_column]
+            .into_iter()
+            .chain(self.id_column)
+            .chain(self.payload_columns.iter().copied())
+    }
+
+    fn column<'b>(&self, batch: &'b RecordBatch, name: &str) -> Option<&'b dyn Array> {
+        batch.column_by_name(name).map(|c| c.as_ref())
+    }
+
+    // the payload columns of every row as json objects, arrow already knows how
+    // to turn every type into json so this just goes through its writer
+    fn payload_rows(&self, batch: &RecordBatch) -> Result<Vec<Payload>, ImportError> {
+        let mut indices = vec![];
+        for name in &self.payload_columns {
+            match batch.schema().index_of(name) {
+                Ok(i) => indices.push(i),
+                Err(_) => return Err(ImportError::ColumnError(name.to_string())),
+            }
+        }
+
+        let mut writer = ArrayWriter::new(vec![]);
+        writer.write(&batch.project(&indices)?)?;
+        writer.finish()?;
+        Ok(serde_json::from_slice(&writer.into_inner())?)
+    }
+}
+
+/// every row of a fixed size list (or list) of f32 column, straight out of the
+/// column's buffer. none if its some other type or has any nulls
+pub(crate) fn vector_rows(column: &dyn Array) -> Option<Vec<&[f32]>> {
+    if column.null_count() > 0 {
+        return None;
+    }
+
+    match column.data_type() {
+        DataType::FixedSizeList(..) => {
+            let list = column.as_fixed_size_list();
+            let values = floats(list.values().as_ref())?;
+            let len = list.value_length() as usize;
+            Some(
+                (0..list.len())
+                    .map(|i| {
+                        let start = list.value_offset(i) as usize;
+                        &values[start..start + len]
+                    })
+                    .collect(),
+            )
+        }
+        DataType::List(_) => {
+            let list = column.as_list::<i32>();
+            let values = floats(list.values().as_ref())?;
+            Some(
+                list.value_offsets()
+                    .windows(2)
+                    .map(|w| &values[w[0] as usize..w[1] as usize])
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn floats(values: &dyn Array) -> Option<&[f32]> {
+    match values.null_count() {
+        0 => values
+            .as_primitive_opt::<Float32Type>()
+            .map(|v| v.values().as_ref()),
+        _ => None,
+    }
+}
+
+fn id_rows(column: &dyn Array) -> Option<Vec<Uuid>> {
+    if column.null_count() > 0 {
+        return None;
+    }
+
+    match column.data_type() {
+        DataType::Utf8 => column
+            .as_string::<i32>()
+            .iter()
+            .map(|id| Uuid::parse_str(id?).ok())
+            .collect(),
+        DataType::FixedSizeBinary(16) => column
+            .as_fixed_size_binary()
+            .iter()
+            .map(|id| Uuid::from_slice(id?).ok())
+            .collect(),
+        DataType::Int64 => Some(
+            column
+                .as_primitive::<Int64Type>()
+                .values()
+                .iter()
+                .map(|id| Uuid::from_u128(*id as u64 as u128))
+                .collect(),
+        ),
+        DataType::UInt64 => Some(
+            column
+                .as_primitive::<UInt64Type>()
+                .values()
+                .iter()
+                .map(|id| Uuid::from_u128(*id as u128))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
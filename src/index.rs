@@ -1,23 +1,25 @@
 use std::collections::HashMap;
 
-use crate::storage_manager::{self, ItemId, StorageManager};
+use zerocopy::{FromBytes, IntoBytes, TryFromBytes};
 
-enum IndexError {
+use crate::storage_manager::ItemId;
+
+#[derive(Debug)]
+pub enum IndexError {
     InvalidLevel,
     InvalidItemId,
+    DecodeError,
 }
 
+// the whole index is held in memory, it gets read from the end of the file on
+// open and written back there on flush (see notes.md)
 pub struct Index {
     levels: Vec<HashMap<ItemId, Vec<Conn>>>,
-    storage_manager: StorageManager,
 }
 
 impl Index {
-    pub fn new(storage_manager: StorageManager) -> Self {
-        Self {
-            levels: vec![],
-            storage_manager,
-        }
+    pub fn new() -> Self {
+        Self { levels: vec![] }
     }
 
     pub fn get_conns(&self, node: ItemId, level: usize) -> Result<&[Conn], IndexError> {
@@ -56,6 +58,134 @@ impl Index {
         }
     }
 
+    /// replaces the connections of `node` at `level`, used when pruning an
+    /// overfull neighbor list
+    pub fn set_conns(
+        &mut self,
+        node: ItemId,
+        level: usize,
+        conns: Vec<Conn>,
+    ) -> Result<(), IndexError> {
+        match self.levels.get_mut(level) {
+            Some(conn_map) => match conn_map.get_mut(&node) {
+                Some(old) => {
+                    *old = conns;
+                    Ok(())
+                }
+                None => Err(IndexError::InvalidItemId),
+            },
+            None => Err(IndexError::InvalidLevel),
+        }
+    }
+
+    /// takes `node` out of every level, along with any connections pointing at it,
+    /// and drops any levels that end up empty
+    pub fn remove_item(&mut self, node: ItemId) {
+        for conn_map in self.levels.iter_mut() {
+            if conn_map.remove(&node).is_some() {
+                // connections aren't always symmetric after pruning, so we have
+                // to look at everyone
+                for conns in conn_map.values_mut() {
+                    conns.retain(|c| c.other != node);
+                }
+            }
+        }
+        while self.levels.last().is_some_and(|l| l.is_empty()) {
+            self.levels.pop();
+        }
+    }
+
+    /// some node on the highest level, for when the entry point goes away
+    pub fn top(&self) -> Option<(ItemId, usize)> {
+        let level = self.levels.len().checked_sub(1)?;
+        self.levels[level].keys().next().map(|id| (*id, level))
+    }
+
+    /// rewrites every id that shows up in `moved`, used after compaction
+    pub fn remap(&mut self, moved: &HashMap<ItemId, ItemId>) {
+        let remap = |id: ItemId| *moved.get(&id).unwrap_or(&id);
+        for conn_map in self.levels.iter_mut() {
+            *conn_map = conn_map
+                .drain()
+                .map(|(id, mut conns)| {
+                    for conn in conns.iter_mut() {
+                        conn.other = remap(conn.other);
+                    }
+                    (remap(id), conns)
+                })
+                .collect();
+        }
+    }
+
+    /// every level as each node's neighbors, for [`crate::graph_stats`]
+    pub fn neighbor_lists(&self) -> Vec<HashMap<ItemId, Vec<ItemId>>> {
+        self.levels
+            .iter()
+            .map(|conn_map| {
+                conn_map
+                    .iter()
+                    .map(|(id, conns)| (*id, conns.iter().map(|c| c.other).collect()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// level count, then for every level the node count and each node as its id,
+    /// its connection count and the connections (id, dist)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from((self.levels.len() as u32).as_bytes());
+        for conn_map in &self.levels {
+            out.extend_from_slice((conn_map.len() as u32).as_bytes());
+            for (id, conns) in conn_map {
+                out.extend_from_slice(id.as_bytes());
+                out.extend_from_slice((conns.len() as u32).as_bytes());
+                for conn in conns {
+                    out.extend_from_slice(conn.other.as_bytes());
+                    out.extend_from_slice(conn.dist.as_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    /// the other way around from [`Index::to_bytes`], no bytes at all is an
+    /// empty index
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IndexError> {
+        let mut rdr = bytes;
+        if rdr.is_empty() {
+            return Ok(Self::new());
+        }
+
+        let mut levels = vec![];
+
+        let num_levels = read_u32(&mut rdr)?;
+        for _ in 0..num_levels {
+            let num_nodes = read_u32(&mut rdr)?;
+            let mut conn_map = HashMap::with_capacity(num_nodes as usize);
+            for _ in 0..num_nodes {
+                let id = read_item_id(&mut rdr)?;
+                let num_conns = read_u32(&mut rdr)?;
+                let mut conns = Vec::with_capacity(num_conns as usize);
+                for _ in 0..num_conns {
+                    let other = read_item_id(&mut rdr)?;
+                    let dist = f32::from_bits(read_u32(&mut rdr)?);
+                    conns.push(Conn { other, dist });
+                }
+                conn_map.insert(id, conns);
+            }
+            levels.push(conn_map);
+        }
+
+        match rdr.is_empty() {
+            true => Ok(Self { levels }),
+            false => Err(IndexError::DecodeError),
+        }
+    }
+
     pub fn push_item(&mut self, new: ItemId, highest_level: usize) {
         for level in 0..=highest_level {
             match self.levels.get_mut(level) {
@@ -72,7 +202,28 @@ impl Index {
     }
 }
 
-struct Conn {
-    other: ItemId,
-    dist: f32,
+#[derive(Clone, Copy)]
+pub struct Conn {
+    pub other: ItemId,
+    pub dist: f32,
+}
+
+fn read_u32(rdr: &mut &[u8]) -> Result<u32, IndexError> {
+    match u32::read_from_prefix(rdr) {
+        Ok((n, rest)) => {
+            *rdr = rest;
+            Ok(n)
+        }
+        Err(_) => Err(IndexError::DecodeError),
+    }
+}
+
+fn read_item_id(rdr: &mut &[u8]) -> Result<ItemId, IndexError> {
+    match ItemId::try_read_from_prefix(rdr) {
+        Ok((id, rest)) => {
+            *rdr = rest;
+            Ok(id)
+        }
+        Err(_) => Err(IndexError::DecodeError),
+    }
 }
@@ -0,0 +1,297 @@
+use std::{
+    collections::BinaryHeap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use zerocopy::{FromBytes, IntoBytes};
+
+use crate::{
+    distance_calculators::DistanceCalculator,
+    quantization::{kmeans, Encoding},
+    storage_manager::{Header, ItemId, StorageManager, StorageManagerError},
+    utils::MaxDist,
+    vector_pool::{VectorPool, VectorPoolError},
+};
+
+/// inverted file index, every vector goes in the posting list of its nearest
+/// k-means centroid and each list gets its own pages. a search only reads the
+/// pages of the `nprobe` lists closest to the query, so nothing but the
+/// centroids has to stay in memory
+pub struct IVF {
+    path: PathBuf,
+    dimension: usize,
+    dist_calc: Box<dyn DistanceCalculator>,
+    header: Header,
+    storage_manager: StorageManager,
+    vector_pool: VectorPool,
+    // nlist * dimension, list by list
+    centroids: Vec<f32>,
+    // the pages each posting list lives in
+    lists: Vec<Vec<u32>>,
+}
+
+#[derive(Debug)]
+pub enum IVFError {
+    SMError(StorageManagerError),
+    VPError(VectorPoolError),
+    EmbSizeError,
+    ParamsError,
+    // the file was built with a different distance calculator
+    MetricMismatchError,
+    EncoderSizeError,
+    IoError(io::Error),
+}
+
+impl From<StorageManagerError> for IVFError {
+    fn from(e: StorageManagerError) -> Self {
+        Self::SMError(e)
+    }
+}
+
+impl From<VectorPoolError> for IVFError {
+    fn from(e: VectorPoolError) -> Self {
+        Self::VPError(e)
+    }
+}
+
+impl From<io::Error> for IVFError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl IVF {
+    /// trains `nlist` centroids on `sample` and makes a new (empty) index file at
+    /// `path`, the sample doesn't get inserted. the centroids are trained with
+    /// `dist_calc` too, so lists get picked the same way they were built
+    pub fn create(
+        path: &str,
+        dimension: u32,
+        nlist: usize,
+        sample: &[&[f32]],
+        iterations: usize,
+        mut dist_calc: Box<dyn DistanceCalculator>,
+    ) -> Result<Self, IVFError> {
+        if nlist == 0 || sample.is_empty() {
+            return Err(IVFError::ParamsError);
+        }
+        if sample.iter().any(|v| v.len() != dimension as usize) {
+            return Err(IVFError::EmbSizeError);
+        }
+
+        let centroids: Vec<f32> = kmeans(sample, nlist, iterations, dist_calc.as_mut())
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut encoder = Vec::from((nlist as u32).as_bytes());
+        encoder.extend_from_slice(centroids.as_bytes());
+
+        // the index fields don't mean anything here
+        let header = Header::new(
+            dimension,
+            Encoding::F32.vec_size(dimension as usize) as u32,
+            Encoding::F32.id(),
+            dist_calc.id(),
+        );
+        let (sm, header) = StorageManager::create(Path::new(path), header, &encoder)?;
+
+        Self::from_parts(path, sm, header, centroids, vec![vec![]; nlist], dist_calc)
+    }
+
+    pub fn open(path: &str, dist_calc: Box<dyn DistanceCalculator>) -> Result<Self, IVFError> {
+        let (mut sm, header, encoder) = StorageManager::open(Path::new(path))?;
+        // the centroids were trained with it
+        if header.dist_id != dist_calc.id() {
+            return Err(IVFError::MetricMismatchError);
+        }
+        let dimension = header.dim as usize;
+
+        let (nlist, centroids) = match u32::read_from_prefix(&encoder) {
+            Ok((nlist, rest)) if rest.len() == nlist as usize * dimension * 4 => (
+                nlist as usize,
+                rest.chunks_exact(4)
+                    .map(|c| f32::read_from_bytes(c).unwrap())
+                    .collect::<Vec<_>>(),
+            ),
+            _ => return Err(IVFError::EncoderSizeError),
+        };
+        let lists = match Self::lists_from_bytes(&sm.read_index()?, nlist) {
+            Some(lists) => lists,
+            None => return Err(IVFError::EncoderSizeError),
+        };
+
+        Self::from_parts(path, sm, header, centroids, lists, dist_calc)
+    }
+
+    fn from_parts(
+        path: &str,
+        mut storage_manager: StorageManager,
+        header: Header,
+        centroids: Vec<f32>,
+        lists: Vec<Vec<u32>>,
+        dist_calc: Box<dyn DistanceCalculator>,
+    ) -> Result<Self, IVFError> {
+        let vector_pool = VectorPool::for_file(&mut storage_manager, &header)?;
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            dimension: header.dim as usize,
+            dist_calc,
+            header,
+            storage_manager,
+            vector_pool,
+            centroids,
+            lists,
+        })
+    }
+
+    pub fn nlist(&self) -> usize {
+        self.lists.len()
+    }
+
+    /// goes through every vector in the `nprobe` posting lists nearest to
+    /// `query`, more lists is better recall for more pages read
+    pub fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        nprobe: usize,
+    ) -> Result<Vec<(ItemId, f32)>, IVFError> {
+        if query.len() != self.dimension {
+            return Err(IVFError::EmbSizeError);
+        }
+
+        let mut found = BinaryHeap::<MaxDist<ItemId>>::new();
+        for list in self.nearest_lists(query, nprobe) {
+            for page_number in &self.lists[list] {
+                self.vector_pool.scan_page(
+                    *page_number,
+                    &mut self.storage_manager,
+                    |id, data| {
+                        let v = <[f32]>::ref_from_bytes(data).unwrap();
+                        let dist = self.dist_calc.calc_dist(query, v);
+                        if found.len() < top_k || found.peek().is_some_and(|f| dist < f.dist) {
+                            found.push(MaxDist { dist, id });
+                            if found.len() > top_k {
+                                found.pop();
+                            }
+                        }
+                    },
+                )?;
+            }
+        }
+
+        Ok(found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|f| (f.id, f.dist))
+            .collect())
+    }
+
+    pub fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, IVFError> {
+        if new_data.len() != self.dimension {
+            return Err(IVFError::EmbSizeError);
+        }
+
+        let list = self.nearest_lists(new_data, 1)[0];
+        let id = self.vector_pool.push_bytes_in(
+            &self.lists[list],
+            new_data.as_bytes(),
+            &mut self.storage_manager,
+        )?;
+        let page_number = id.page_number;
+        if !self.lists[list].contains(&page_number) {
+            self.lists[list].push(page_number);
+        }
+
+        Ok(id)
+    }
+
+    /// the slot just gets freed, pages stay with their list so the next insert
+    /// into that list can fill it back up
+    pub fn delete(&mut self, id: ItemId) -> Result<(), IVFError> {
+        self.vector_pool.delete(id, &mut self.storage_manager)?;
+        Ok(())
+    }
+
+    pub fn get(&mut self, id: ItemId) -> Result<Vec<f32>, IVFError> {
+        Ok(self
+            .vector_pool
+            .get(id, &mut self.storage_manager)?
+            .to_vec())
+    }
+
+    pub fn len(&self) -> usize {
+        self.vector_pool.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// writes the dirty pages, the posting lists and the header out to the file
+    pub fn flush(&mut self) -> Result<(), IVFError> {
+        self.vector_pool.flush(&mut self.storage_manager)?;
+        self.storage_manager
+            .write_tail(self.vector_pool.free_space(), &self.lists_to_bytes())?;
+        self.storage_manager.write_header(&mut self.header)?;
+        Ok(())
+    }
+
+    /// flushes, and copies the file to `path` if thats somewhere else
+    pub fn save(&mut self, path: &Path) -> Result<(), IVFError> {
+        self.flush()?;
+        if path != self.path {
+            fs::copy(&self.path, path)?;
+        }
+        Ok(())
+    }
+
+    /// the `n` lists with centroids closest to `query`, closest first
+    fn nearest_lists(&mut self, query: &[f32], n: usize) -> Vec<usize> {
+        let mut dists = self
+            .centroids
+            .chunks_exact(self.dimension)
+            .map(|c| self.dist_calc.calc_dist(query, c))
+            .enumerate()
+            .collect::<Vec<_>>();
+        dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+        dists.into_iter().take(n).map(|(i, _)| i).collect()
+    }
+
+    // every list as its page count and then the pages
+    fn lists_to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for list in &self.lists {
+            out.extend_from_slice((list.len() as u32).as_bytes());
+            out.extend_from_slice(list.as_bytes());
+        }
+        out
+    }
+
+    fn lists_from_bytes(bytes: &[u8], nlist: usize) -> Option<Vec<Vec<u32>>> {
+        if bytes.is_empty() {
+            return Some(vec![vec![]; nlist]);
+        }
+
+        let mut rdr = bytes;
+        let mut lists = Vec::with_capacity(nlist);
+        for _ in 0..nlist {
+            let (len, rest) = u32::read_from_prefix(rdr).ok()?;
+            let (pages, rest) = rest.split_at_checked(len as usize * 4)?;
+            lists.push(
+                pages
+                    .chunks_exact(4)
+                    .map(|p| u32::read_from_bytes(p).unwrap())
+                    .collect(),
+            );
+            rdr = rest;
+        }
+
+        match rdr.is_empty() {
+            true => Some(lists),
+            false => None,
+        }
+    }
+}
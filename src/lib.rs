@@ -1,9 +1,20 @@
 #![cfg_attr(feature = "simd", feature(portable_simd))]
 
 pub mod distance_calculators;
+pub mod eval;
+pub mod flat;
+pub mod graph_stats;
 pub mod hnsw;
+pub mod import;
 mod index;
+pub mod ivf;
+pub mod quantization;
+pub mod search;
 mod storage_manager;
 pub mod tinyworld;
 mod utils;
+pub mod vamana;
+pub mod vector_index;
 mod vector_pool;
+
+pub use storage_manager::ItemId;
@@ -0,0 +1,452 @@
+use half::{bf16, f16, slice::HalfBitsSliceExt};
+use rand::seq::SliceRandom;
+use zerocopy::{FromBytes, IntoBytes};
+
+use crate::distance_calculators::{DistanceCalculator, Hamming, SquaredEuclidean};
+
+/// how vectors get stored in the vector pages, the id is what goes in the header
+pub enum Encoding {
+    F32,
+    SQ8(ScalarQuantizer),
+    PQ(ProductQuantizer),
+    // one sign bit per dimension
+    Binary,
+    // half precision, widened back to f32 when computing distances
+    F16,
+    BF16,
+}
+
+/// whatever an encoding needs per query to compare it against stored codes
+#[derive(Default)]
+pub struct PreparedQuery {
+    // pq distance table
+    adc_table: Vec<f32>,
+    // binary code of the query
+    code: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum EncodingError {
+    UnknownEncoding,
+    EncoderSizeError,
+    SubspaceError,
+}
+
+impl Encoding {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::F32 => 0,
+            Self::SQ8(_) => 1,
+            Self::PQ(_) => 2,
+            Self::Binary => 3,
+            Self::F16 => 4,
+            Self::BF16 => 5,
+        }
+    }
+
+    /// bytes per stored vector
+    pub fn vec_size(&self, dim: usize) -> usize {
+        match self {
+            Self::F32 => dim * 4,
+            Self::SQ8(_) => dim,
+            Self::PQ(pq) => pq.subspaces,
+            Self::Binary => dim.div_ceil(8),
+            Self::F16 | Self::BF16 => dim * 2,
+        }
+    }
+
+    /// whatever needs to get saved alongside the header to decode vectors
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::F32 => vec![],
+            Self::SQ8(sq) => sq.to_bytes(),
+            Self::PQ(pq) => pq.to_bytes(),
+            Self::Binary | Self::F16 | Self::BF16 => vec![],
+        }
+    }
+
+    pub fn from_bytes(id: u8, dim: usize, bytes: &[u8]) -> Result<Self, EncodingError> {
+        match id {
+            0 => Ok(Self::F32),
+            1 => Ok(Self::SQ8(ScalarQuantizer::from_bytes(dim, bytes)?)),
+            2 => Ok(Self::PQ(ProductQuantizer::from_bytes(dim, bytes)?)),
+            3 => Ok(Self::Binary),
+            4 => Ok(Self::F16),
+            5 => Ok(Self::BF16),
+            _ => Err(EncodingError::UnknownEncoding),
+        }
+    }
+
+    pub fn encode(&self, v: &[f32], out: &mut [u8]) {
+        match self {
+            Self::F32 => out.copy_from_slice(v.as_bytes()),
+            Self::SQ8(sq) => sq.encode(v, out),
+            Self::PQ(pq) => pq.encode(v, out),
+            Self::Binary => binarize(v, out),
+            Self::F16 => {
+                for (o, x) in out.chunks_exact_mut(2).zip(v) {
+                    o.copy_from_slice(&f16::from_f32(*x).to_bits().to_ne_bytes());
+                }
+            }
+            Self::BF16 => {
+                for (o, x) in out.chunks_exact_mut(2).zip(v) {
+                    o.copy_from_slice(&bf16::from_f32(*x).to_bits().to_ne_bytes());
+                }
+            }
+        }
+    }
+
+    /// sets up `prepared` for `query`, has to be called before
+    /// [`Encoding::calc_dist`] gets used with a new query
+    pub fn prepare_query(
+        &self,
+        query: &[f32],
+        dist_calc: &mut dyn DistanceCalculator,
+        prepared: &mut PreparedQuery,
+    ) {
+        match self {
+            Self::PQ(pq) => pq.distance_table(query, dist_calc, &mut prepared.adc_table),
+            Self::Binary => {
+                prepared.code.resize(self.vec_size(query.len()), 0);
+                binarize(query, &mut prepared.code);
+            }
+            Self::F32 | Self::SQ8(_) | Self::F16 | Self::BF16 => {}
+        }
+    }
+
+    /// distance from a full precision query to a stored code
+    pub fn calc_dist(
+        &self,
+        query: &[f32],
+        prepared: &PreparedQuery,
+        code: &[u8],
+        dist_calc: &mut dyn DistanceCalculator,
+    ) -> f32 {
+        match self {
+            Self::F32 => dist_calc.calc_dist(query, <[f32]>::ref_from_bytes(code).unwrap()),
+            Self::SQ8(sq) => dist_calc.calc_dist_sq8(query, code, sq),
+            Self::PQ(pq) => pq.adc(&prepared.adc_table, code),
+            // the graph gets walked on hamming distance no matter the metric,
+            // re-ranking is what brings the real metric back in
+            Self::Binary => Hamming::calc_dist_bits(&prepared.code, code),
+            Self::F16 => {
+                let v = <[u16]>::ref_from_bytes(code).unwrap();
+                dist_calc.calc_dist_f16(query, v.reinterpret_cast())
+            }
+            Self::BF16 => {
+                let v = <[u16]>::ref_from_bytes(code).unwrap();
+                dist_calc.calc_dist_bf16(query, v.reinterpret_cast())
+            }
+        }
+    }
+
+    /// back to f32, lossy for everything but `F32`. binary codes come back as
+    /// +1/-1 per dimension
+    pub fn decode(&self, code: &[u8], out: &mut [f32]) {
+        match self {
+            Self::F32 => out.as_mut_bytes().copy_from_slice(code),
+            Self::SQ8(sq) => sq.decode(code, out),
+            Self::PQ(pq) => pq.decode(code, out),
+            Self::Binary => {
+                for (i, o) in out.iter_mut().enumerate() {
+                    *o = match code[i / 8] & (1 << (i % 8)) {
+                        0 => -1.0,
+                        _ => 1.0,
+                    };
+                }
+            }
+            Self::F16 => {
+                for (o, c) in out.iter_mut().zip(code.chunks_exact(2)) {
+                    *o = f16::from_bits(u16::from_ne_bytes([c[0], c[1]])).to_f32();
+                }
+            }
+            Self::BF16 => {
+                for (o, c) in out.iter_mut().zip(code.chunks_exact(2)) {
+                    *o = bf16::from_bits(u16::from_ne_bytes([c[0], c[1]])).to_f32();
+                }
+            }
+        }
+    }
+}
+
+/// packs the sign of every dimension into bits, positive is 1
+pub fn binarize(v: &[f32], out: &mut [u8]) {
+    out.fill(0);
+    for (i, x) in v.iter().enumerate() {
+        if *x > 0.0 {
+            out[i / 8] |= 1 << (i % 8);
+        }
+    }
+}
+
+/// int8 scalar quantization, every dimension gets mapped from [min, max] onto
+/// 0..=255. the range is either per dimension or one for the whole vector
+pub struct ScalarQuantizer {
+    mins: Vec<f32>,
+    scales: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    /// calibrates the ranges from a sample of vectors, anything outside of the
+    /// range later on just gets clamped
+    pub fn fit<'v>(sample: impl IntoIterator<Item = &'v [f32]>, dim: usize, per_dim: bool) -> Self {
+        let mut mins = vec![f32::MAX; dim];
+        let mut maxs = vec![f32::MIN; dim];
+        for v in sample {
+            for (i, x) in v.iter().enumerate() {
+                mins[i] = mins[i].min(*x);
+                maxs[i] = maxs[i].max(*x);
+            }
+        }
+
+        if !per_dim {
+            let min = mins.iter().copied().fold(f32::MAX, f32::min);
+            let max = maxs.iter().copied().fold(f32::MIN, f32::max);
+            mins.fill(min);
+            maxs.fill(max);
+        }
+
+        let scales = mins
+            .iter()
+            .zip(maxs.iter())
+            .map(|(min, max)| match max > min {
+                true => (max - min) / 255.0,
+                // empty sample or a constant dimension
+                false => 1.0,
+            })
+            .collect();
+        let mins = mins
+            .into_iter()
+            .map(|m| match m == f32::MAX {
+                true => 0.0,
+                false => m,
+            })
+            .collect();
+
+        Self { mins, scales }
+    }
+
+    pub fn encode(&self, v: &[f32], out: &mut [u8]) {
+        for (((o, x), min), scale) in out.iter_mut().zip(v).zip(&self.mins).zip(&self.scales) {
+            *o = ((x - min) / scale).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    pub fn decode(&self, code: &[u8], out: &mut [f32]) {
+        for (((o, c), min), scale) in out.iter_mut().zip(code).zip(&self.mins).zip(&self.scales) {
+            *o = min + (*c as f32 * scale);
+        }
+    }
+
+    pub fn mins(&self) -> &[f32] {
+        &self.mins
+    }
+
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from(self.mins.as_bytes());
+        out.extend_from_slice(self.scales.as_bytes());
+        out
+    }
+
+    fn from_bytes(dim: usize, bytes: &[u8]) -> Result<Self, EncodingError> {
+        if bytes.len() != dim * 8 {
+            return Err(EncodingError::EncoderSizeError);
+        }
+        let (mins, scales) = bytes.split_at(dim * 4);
+        let read = |b: &[u8]| {
+            b.chunks_exact(4)
+                .map(|c| f32::read_from_bytes(c).unwrap())
+                .collect()
+        };
+
+        Ok(Self {
+            mins: read(mins),
+            scales: read(scales),
+        })
+    }
+}
+
+// one byte codes
+const PQ_CENTROIDS: usize = 256;
+
+/// product quantization, the vector gets split into `subspaces` chunks and each
+/// chunk is stored as the id of its nearest centroid from that chunk's codebook
+pub struct ProductQuantizer {
+    subspaces: usize,
+    sub_dim: usize,
+    // subspaces * PQ_CENTROIDS * sub_dim, codebook by codebook
+    centroids: Vec<f32>,
+}
+
+impl ProductQuantizer {
+    /// trains the codebooks with k-means over each subspace of `sample`
+    pub fn train(
+        sample: &[&[f32]],
+        dim: usize,
+        subspaces: usize,
+        iterations: usize,
+    ) -> Result<Self, EncodingError> {
+        if subspaces == 0 || !dim.is_multiple_of(subspaces) || sample.is_empty() {
+            return Err(EncodingError::SubspaceError);
+        }
+        let sub_dim = dim / subspaces;
+
+        let mut centroids = Vec::with_capacity(subspaces * PQ_CENTROIDS * sub_dim);
+        for s in 0..subspaces {
+            let points: Vec<&[f32]> = sample
+                .iter()
+                .map(|v| &v[s * sub_dim..(s + 1) * sub_dim])
+                .collect();
+            // l2 to match `nearest` in encode
+            let book = kmeans(&points, PQ_CENTROIDS, iterations, &mut SquaredEuclidean {});
+            centroids.extend(book.into_iter().flatten());
+        }
+
+        Ok(Self {
+            subspaces,
+            sub_dim,
+            centroids,
+        })
+    }
+
+    pub fn encode(&self, v: &[f32], out: &mut [u8]) {
+        for (s, o) in out.iter_mut().enumerate() {
+            let sub = &v[s * self.sub_dim..(s + 1) * self.sub_dim];
+            let book = self.codebook(s).chunks_exact(self.sub_dim);
+            *o = nearest(book, sub) as u8;
+        }
+    }
+
+    pub fn decode(&self, code: &[u8], out: &mut [f32]) {
+        for (s, (c, o)) in code
+            .iter()
+            .zip(out.chunks_exact_mut(self.sub_dim))
+            .enumerate()
+        {
+            o.copy_from_slice(self.centroid(s, *c as usize));
+        }
+    }
+
+    /// the asymmetric distance table for `query`, the distance from each query
+    /// chunk to every centroid of that chunk's codebook. this only works out for
+    /// distances that add up over the dimensions (dot product, squared l2)
+    pub fn distance_table(
+        &self,
+        query: &[f32],
+        dist_calc: &mut dyn DistanceCalculator,
+        table: &mut Vec<f32>,
+    ) {
+        table.clear();
+        for s in 0..self.subspaces {
+            let sub = &query[s * self.sub_dim..(s + 1) * self.sub_dim];
+            for c in 0..PQ_CENTROIDS {
+                table.push(dist_calc.calc_dist(sub, self.centroid(s, c)));
+            }
+        }
+    }
+
+    /// distance from the query a table was built for to a code
+    pub fn adc(&self, table: &[f32], code: &[u8]) -> f32 {
+        code.iter()
+            .enumerate()
+            .map(|(s, c)| table[(s * PQ_CENTROIDS) + *c as usize])
+            .sum()
+    }
+
+    fn codebook(&self, s: usize) -> &[f32] {
+        let book_size = PQ_CENTROIDS * self.sub_dim;
+        &self.centroids[s * book_size..(s + 1) * book_size]
+    }
+
+    fn centroid(&self, s: usize, c: usize) -> &[f32] {
+        &self.codebook(s)[c * self.sub_dim..(c + 1) * self.sub_dim]
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from((self.subspaces as u32).as_bytes());
+        out.extend_from_slice(self.centroids.as_bytes());
+        out
+    }
+
+    fn from_bytes(dim: usize, bytes: &[u8]) -> Result<Self, EncodingError> {
+        if bytes.len() < 4 {
+            return Err(EncodingError::EncoderSizeError);
+        }
+        let (subspaces, centroids) = bytes.split_at(4);
+        let subspaces = u32::read_from_bytes(subspaces).unwrap() as usize;
+        if subspaces == 0 || !dim.is_multiple_of(subspaces) {
+            return Err(EncodingError::SubspaceError);
+        }
+        if centroids.len() != subspaces * PQ_CENTROIDS * (dim / subspaces) * 4 {
+            return Err(EncodingError::EncoderSizeError);
+        }
+
+        Ok(Self {
+            subspaces,
+            sub_dim: dim / subspaces,
+            centroids: centroids
+                .chunks_exact(4)
+                .map(|c| f32::read_from_bytes(c).unwrap())
+                .collect(),
+        })
+    }
+}
+
+/// k-means with points assigned to their closest centroid by `dist_calc`,
+/// starting from random points. the centroids are still plain means, so this
+/// is only really k-means for l2. if there's less points than `k` some
+/// centroids just end up doubled
+pub(crate) fn kmeans(
+    points: &[&[f32]],
+    k: usize,
+    iterations: usize,
+    dist_calc: &mut dyn DistanceCalculator,
+) -> Vec<Vec<f32>> {
+    let dim = points[0].len();
+    let mut book: Vec<Vec<f32>> = (0..k).map(|i| points[i % points.len()].to_vec()).collect();
+    book.shuffle(&mut rand::thread_rng());
+
+    let mut assignments = vec![0; points.len()];
+    for _ in 0..iterations {
+        for (a, p) in assignments.iter_mut().zip(points) {
+            *a = book
+                .iter()
+                .map(|c| dist_calc.calc_dist(p, c))
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+                .0;
+        }
+
+        let mut sums = vec![vec![0.0; dim]; k];
+        let mut counts = vec![0; k];
+        for (a, p) in assignments.iter().zip(points) {
+            counts[*a] += 1;
+            for (s, x) in sums[*a].iter_mut().zip(p.iter()) {
+                *s += x;
+            }
+        }
+        for ((c, s), n) in book.iter_mut().zip(sums).zip(counts) {
+            // empty clusters just keep their old centroid
+            if n > 0 {
+                *c = s.into_iter().map(|x| x / n as f32).collect();
+            }
+        }
+    }
+
+    book
+}
+
+/// index of the closest (l2) point in `book` to `p`
+fn nearest<'b>(book: impl IntoIterator<Item = &'b [f32]>, p: &[f32]) -> usize {
+    book.into_iter()
+        .map(|c| c.iter().zip(p).map(|(a, b)| (a - b) * (a - b)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+        .0
+}
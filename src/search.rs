@@ -0,0 +1,98 @@
+use uuid::Uuid;
+
+/// whatever got stored alongside a vector
+pub type Payload = serde_json::Value;
+
+pub struct SearchHit<Id = Uuid> {
+    pub id: Id,
+    // straight from the distance calculator, smaller is closer
+    pub distance: f32,
+    // see [`crate::distance_calculators::DistanceCalculator::score`], bigger is
+    // more similar no matter the metric
+    pub score: f32,
+    // only there if asked for in the [`SearchOptions`]
+    pub vector: Option<Vec<f32>>,
+    pub payload: Option<Payload>,
+}
+
+type Filter<'f, Id> = Box<dyn Fn(&Id, Option<&Payload>) -> bool + 'f>;
+
+/// everything a search can take besides the query, built up like
+/// `SearchOptions::new(10).ef(100).include_vectors(true)`
+pub struct SearchOptions<'f, Id = Uuid> {
+    pub(crate) top_k: usize,
+    // 0 means just use top_k
+    pub(crate) ef: usize,
+    pub(crate) filters: Vec<Filter<'f, Id>>,
+    pub(crate) include_vectors: bool,
+    pub(crate) include_payloads: bool,
+}
+
+impl<'f, Id> SearchOptions<'f, Id> {
+    pub fn new(top_k: usize) -> Self {
+        Self {
+            top_k,
+            ef: 0,
+            filters: vec![],
+            include_vectors: false,
+            include_payloads: false,
+        }
+    }
+
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// search list size, bumped up to `top_k` if it's less
+    pub fn ef(mut self, ef: usize) -> Self {
+        self.ef = ef;
+        self
+    }
+
+    /// only hits `filter` returns true for come back, with more than one filter
+    /// they all have to pass. filtered out vectors still get walked through
+    /// during the search, they just don't take up any of the `top_k`
+    pub fn filter(mut self, filter: impl Fn(&Id, Option<&Payload>) -> bool + 'f) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn include_vectors(mut self, include_vectors: bool) -> Self {
+        self.include_vectors = include_vectors;
+        self
+    }
+
+    pub fn include_payloads(mut self, include_payloads: bool) -> Self {
+        self.include_payloads = include_payloads;
+        self
+    }
+
+    pub(crate) fn accepts(&self, id: &Id, payload: Option<&Payload>) -> bool {
+        self.filters.iter().all(|f| f(id, payload))
+    }
+}
+
+/// what a search did, see [`crate::hnsw::HNSW::search_with_stats`]
+#[derive(Debug, Clone, Default)]
+pub struct SearchStats {
+    pub dist_evals: usize,
+    // candidates whose neighbors got looked at
+    pub hops: usize,
+    // nodes visited on each level, level 0 first
+    pub visited: Vec<usize>,
+    // the most candidates waiting at once on any level
+    pub candidates_peak: usize,
+    // why the level 0 search stopped
+    pub stop_reason: StopReason,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StopReason {
+    // the closest candidate left was further than everything found, so nothing
+    // after it could get in
+    Converged,
+    // ran out of candidates, everything reachable got looked at
+    #[default]
+    Exhausted,
+}
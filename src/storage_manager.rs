@@ -14,9 +14,11 @@ pub struct StorageManager {
     page_size: u8, // kb
     // TODO: this is dirty
     num_pages: u32,
+    // where the pages start, after the header and encoder
+    data_start: u64,
 }
 
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(
     // bruh
     TryFromBytes,
@@ -26,92 +28,157 @@ pub struct StorageManager {
     IntoBytes,
     PartialEq,
     Eq,
+    PartialOrd,
+    Ord,
     Hash,
     Clone,
     Copy,
+    Debug,
 )]
 pub struct ItemId {
     pub slot_number: u32,
     pub page_number: u32,
 }
 
-const HEADER_SIZE: usize = 32;
-#[repr(packed)]
+const HEADER_SIZE: usize = size_of::<Header>();
+#[repr(C, packed)]
 #[derive(TryFromBytes, Immutable, KnownLayout, Unaligned, IntoBytes)]
 pub struct Header {
     // for the storage manager
-    page_size: u8, // in kb
-    num_pages: u32,
+    pub page_size: u8, // in kb
+    pub num_pages: u32,
 
     // for the vector pool
-    vec_page_slots: u32,
-    dim: u32,
+    pub vec_page_slots: u32,
+    pub dim: u32,
+    // bytes per stored vector, depends on the encoding
+    pub vec_size: u32,
+    // see [`crate::quantization::Encoding`], whatever the encoding needs to decode
+    // (calibration, codebooks etc) sits right after the header
+    pub encoding: u8,
+    pub encoder_size: u32,
+    // whether there's a sibling file with the full precision vectors
+    pub raw_vectors: u8,
 
     // for the index, probably wont end up being in the header
     // TODO: also need to add a listing of index pages since there might be multiple
-    m_max: u8,
-    m0_max: u8,
-    m: u8,
-    m_l: f32,
-    dist_id: u32,
-    ep: ItemId,
+    pub m_max: u8,
+    pub m0_max: u8,
+    pub m: u8,
+    pub m_l: f32,
+    pub ef_construction: u32,
+    // see [`crate::hnsw::FixedParams`], 0 or 1
+    pub neighbor_heuristic: u8,
+    pub extend_candidates: u8,
+    pub keep_pruned_connections: u8,
+    pub dist_id: u32,
+    pub ep: ItemId,
+}
+
+impl Header {
+    /// everything the storage manager fills in (and the index fields) zeroed,
+    /// backends set whichever index fields they use on top of this
+    pub fn new(dim: u32, vec_size: u32, encoding: u8, dist_id: u32) -> Self {
+        Self {
+            page_size: 0,
+            num_pages: 0,
+            vec_page_slots: 0,
+            dim,
+            vec_size,
+            encoding,
+            encoder_size: 0,
+            raw_vectors: 0,
+            m_max: 0,
+            m0_max: 0,
+            m: 0,
+            m_l: 0.0,
+            ef_construction: 0,
+            neighbor_heuristic: 0,
+            extend_candidates: 0,
+            keep_pruned_connections: 0,
+            dist_id,
+            ep: ItemId {
+                page_number: 0,
+                slot_number: 0,
+            },
+        }
+    }
+}
+
+// page sizes are stored in kb in the header
+const KB: usize = 1000;
+
+/// byte offset of the first vector in a vector page, the slot flags come first
+/// and get padded out so the vectors stay aligned for f32
+pub fn vec_offset(slots_per_page: usize) -> usize {
+    slots_per_page.next_multiple_of(4)
 }
 
 #[derive(Debug)]
 pub enum StorageManagerError {
-    FileTypeError,
-    ZeroCopyError,
-    IoError(io::Error),
+    FileType,
+    ZeroCopy,
+    PageSize,
+    Io(io::Error),
+}
+
+impl From<io::Error> for StorageManagerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
 }
 
 impl StorageManager {
-    pub fn open(path: &Path) -> Result<(Self, Header), StorageManagerError> {
+    pub fn open(path: &Path) -> Result<(Self, Header, Vec<u8>), StorageManagerError> {
         match path.extension() {
             Some(e) => {
-                if !(e == "tw") {
-                    return Err(StorageManagerError::FileTypeError);
+                if e != "tw" {
+                    return Err(StorageManagerError::FileType);
                 }
             }
-            None => return Err(StorageManagerError::FileTypeError),
+            None => return Err(StorageManagerError::FileType),
         }
         let mut file = match OpenOptions::new().read(true).write(true).open(path) {
             Ok(f) => f,
-            Err(e) => return Err(StorageManagerError::IoError(e)),
+            Err(e) => return Err(StorageManagerError::Io(e)),
         };
 
         let mut header_buff = [0; HEADER_SIZE];
-        file.read_exact(&mut header_buff).unwrap();
+        file.read_exact(&mut header_buff)?;
         let header = match Header::try_read_from_bytes(&header_buff) {
             Ok(h) => h,
-            Err(_) => return Err(StorageManagerError::ZeroCopyError),
+            Err(_) => return Err(StorageManagerError::ZeroCopy),
         };
 
+        let mut encoder = vec![0; header.encoder_size as usize];
+        file.read_exact(&mut encoder)?;
+
         Ok((
             Self {
                 file,
                 page_size: header.page_size,
                 num_pages: header.num_pages,
+                data_start: (HEADER_SIZE + encoder.len()) as u64,
             },
             header,
+            encoder,
         ))
     }
 
+    /// `header.page_size` is filled in here, and so is `header.vec_page_slots`
+    /// unless it's already set
     pub fn create(
         path: &Path,
-        m_max: u8,
-        m0_max: u8,
-        m: u8,
-        dim: u32,
-        dist_id: u32,
-        m_l: f32,
+        mut header: Header,
+        encoder: &[u8],
     ) -> Result<(Self, Header), StorageManagerError> {
         match path.extension() {
             Some(e) => {
-                if !(e == "tw") {
-                    return Err(StorageManagerError::FileTypeError);
+                if e != "tw" {
+                    return Err(StorageManagerError::FileType);
                 }
             }
-            None => return Err(StorageManagerError::FileTypeError),
+            None => return Err(StorageManagerError::FileType),
         }
         let mut file = match OpenOptions::new()
             .create_new(true)
@@ -120,59 +187,93 @@ impl StorageManager {
             .open(path)
         {
             Ok(f) => f,
-            Err(e) => return Err(StorageManagerError::IoError(e)),
+            Err(e) => return Err(StorageManagerError::Io(e)),
         };
 
-        // TODO:
-        let page_size = 4;
-        let vec_page_slots = 1;
-        let header = Header {
-            num_pages: 0,
-            m_max,
-            m0_max,
-            m_l,
-            m,
-            dim,
-            dist_id,
-            page_size,
-            vec_page_slots,
-            ep: ItemId {
-                page_number: 0,
-                slot_number: 0,
-            },
+        // pick the smallest page (at least 4kb) that fits the vectors, then
+        // pack in as many slots as will fit
+        let vec_size = header.vec_size as usize;
+        let min_slots = usize::max(1, header.vec_page_slots as usize);
+        let page_size = usize::max(
+            4,
+            (vec_offset(min_slots) + min_slots * vec_size).div_ceil(KB),
+        );
+        let page_size = match u8::try_from(page_size) {
+            Ok(p) => p,
+            Err(_) => return Err(StorageManagerError::PageSize),
         };
+        if header.vec_page_slots == 0 {
+            let mut vec_page_slots = 1;
+            while vec_offset(vec_page_slots + 1) + (vec_page_slots + 1) * vec_size
+                <= page_size as usize * KB
+            {
+                vec_page_slots += 1;
+            }
+            header.vec_page_slots = vec_page_slots as u32;
+        }
+        header.page_size = page_size;
+        header.num_pages = 0;
+        header.encoder_size = encoder.len() as u32;
 
         let mut header_buff = [0; HEADER_SIZE];
         header.write_to(&mut header_buff).unwrap();
 
-        file.write_all(&header_buff).unwrap();
+        file.write_all(&header_buff)?;
+        file.write_all(encoder)?;
 
         Ok((
             Self {
                 file,
                 page_size: header.page_size,
                 num_pages: header.num_pages,
+                data_start: (HEADER_SIZE + encoder.len()) as u64,
             },
             header,
         ))
     }
 
-    // yeah just get rid of all these unwraps lol
-    pub fn read_page(&mut self, page: u32, buffer: &mut [u8]) {
-        let offset = HEADER_SIZE as u32 + (page * self.page_size as u32 * 1000);
+    /// page size in bytes
+    pub fn page_size(&self) -> usize {
+        self.page_size as usize * KB
+    }
+
+    /// writes the header back to the start of the file, with the current page count
+    pub fn write_header(&mut self, header: &mut Header) -> Result<(), StorageManagerError> {
+        header.num_pages = self.num_pages;
+
+        let mut header_buff = [0; HEADER_SIZE];
+        header.write_to(&mut header_buff).unwrap();
+
+        match self.file.seek(io::SeekFrom::Start(0)) {
+            Ok(_) => {}
+            Err(e) => return Err(StorageManagerError::Io(e)),
+        }
+        match self.file.write_all(&header_buff) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(StorageManagerError::Io(e)),
+        }
+    }
+
+    fn page_offset(&self, page: u32) -> u64 {
+        self.data_start + (page as u64 * self.page_size() as u64)
+    }
+
+    /// a page past the end of the file (a truncated file) comes back as an
+    /// `UnexpectedEof` error
+    pub fn read_page(&mut self, page: u32, buffer: &mut [u8]) -> io::Result<()> {
+        let offset = self.page_offset(page);
 
-        self.file.seek(io::SeekFrom::Start(offset as u64)).unwrap();
+        self.file.seek(io::SeekFrom::Start(offset))?;
 
-        self.file.read_exact(buffer).unwrap();
+        self.file.read_exact(buffer)
     }
 
-    // add check for offset being past the eof, and also test that to see what happens
-    pub fn write_page(&mut self, page: u32, buffer: &[u8]) {
-        let offset = HEADER_SIZE as u32 + (page * self.page_size as u32 * 1000);
+    pub fn write_page(&mut self, page: u32, buffer: &[u8]) -> io::Result<()> {
+        let offset = self.page_offset(page);
 
-        self.file.seek(io::SeekFrom::Start(offset as u64)).unwrap();
+        self.file.seek(io::SeekFrom::Start(offset))?;
 
-        self.file.write_all(buffer).unwrap();
+        self.file.write_all(buffer)
     }
 
     pub fn new_page(&mut self) -> u32 {
@@ -180,4 +281,50 @@ impl StorageManager {
         self.num_pages += 1;
         page_number
     }
+
+    /// drops every page from `num_pages` on, the file itself gets shortened the
+    /// next time the free space map is written
+    pub fn truncate(&mut self, num_pages: u32) {
+        assert!(num_pages <= self.num_pages);
+        self.num_pages = num_pages;
+    }
+
+    /// the free space map lives right after the last page, one u32 per page with
+    /// the number of free slots in it
+    pub fn read_free_space_map(&mut self) -> Result<Vec<u32>, StorageManagerError> {
+        let mut fsm = vec![0; self.num_pages as usize];
+        if self.num_pages == 0 {
+            return Ok(fsm);
+        }
+
+        let offset = self.page_offset(self.num_pages);
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_exact(fsm.as_mut_bytes())?;
+
+        Ok(fsm)
+    }
+
+    /// whatever comes after the free space map (the serialized index), empty if
+    /// nothing was written there
+    pub fn read_index(&mut self) -> Result<Vec<u8>, StorageManagerError> {
+        let offset = self.page_offset(self.num_pages) + (self.num_pages as u64 * 4);
+        let mut index = vec![];
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_to_end(&mut index)?;
+        Ok(index)
+    }
+
+    /// writes the free space map and the index after the last page, and cuts the
+    /// file off right after them
+    pub fn write_tail(&mut self, fsm: &[u32], index: &[u8]) -> Result<(), StorageManagerError> {
+        assert!(fsm.len() == self.num_pages as usize);
+
+        let offset = self.page_offset(self.num_pages);
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.write_all(fsm.as_bytes())?;
+        self.file.write_all(index)?;
+        self.file
+            .set_len(offset + (fsm.as_bytes().len() + index.len()) as u64)?;
+        Ok(())
+    }
 }
@@ -1,23 +1,642 @@
-use std::path::Path;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
-use crate::storage_manager::{StorageManager, StorageManagerError};
+use rand::rngs::ThreadRng;
+use zerocopy::IntoBytes;
 
-pub struct TinyWorld {}
+use crate::{
+    distance_calculators::DistanceCalculator,
+    graph_stats::{self, GraphIssue, GraphStats},
+    hnsw::{random_level, FixedParams},
+    index::{Conn, Index, IndexError},
+    quantization::{Encoding, EncodingError, PreparedQuery},
+    storage_manager::{Header, ItemId, StorageManager, StorageManagerError},
+    utils::{MaxDist, MinDist},
+    vector_pool::{VectorPool, VectorPoolError},
+};
 
+/// the same hnsw as [`crate::hnsw::HNSW`], but with vectors living in pages of a
+/// `.tw` file and only pulled into memory through the [`VectorPool`]
+pub struct TinyWorld {
+    path: PathBuf,
+    entry: Option<(ItemId, usize)>,
+    rng: ThreadRng,
+    fixed_params: FixedParams,
+    dist_calc: Box<dyn DistanceCalculator>,
+    header: Header,
+    storage_manager: StorageManager,
+    vector_pool: VectorPool,
+    index: Index,
+    encoding: Encoding,
+    encode_buff: Vec<u8>,
+    // whatever the encoding needs for the current query
+    prepared: PreparedQuery,
+    // full precision vectors for re-ranking, kept in a sibling file under the
+    // same ids as the encoded ones
+    raw: Option<RawVectors>,
+    // when re-ranking, how many times top_k candidates to pull before re-ranking
+    oversample: usize,
+}
+
+struct RawVectors {
+    header: Header,
+    storage_manager: StorageManager,
+    vector_pool: VectorPool,
+}
+
+#[derive(Debug)]
 pub enum TWError {
     SMError(StorageManagerError),
+    VPError(VectorPoolError),
+    IndexError(IndexError),
+    EncodingError(EncodingError),
+    EmbSizeError,
+    ParamsError,
+    // the file was built with a different distance calculator
+    MetricMismatchError,
+    // the raw vectors file doesn't line up with the main one anymore
+    RawMismatchError,
+    IoError(io::Error),
+}
+
+impl From<io::Error> for TWError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<StorageManagerError> for TWError {
+    fn from(e: StorageManagerError) -> Self {
+        Self::SMError(e)
+    }
+}
+
+impl From<VectorPoolError> for TWError {
+    fn from(e: VectorPoolError) -> Self {
+        Self::VPError(e)
+    }
+}
+
+impl From<IndexError> for TWError {
+    fn from(e: IndexError) -> Self {
+        Self::IndexError(e)
+    }
+}
+
+impl From<EncodingError> for TWError {
+    fn from(e: EncodingError) -> Self {
+        Self::EncodingError(e)
+    }
 }
 
 impl TinyWorld {
-    pub fn create() -> Self {
-        Self {}
+    pub fn create(
+        path: &str,
+        fixed_params: FixedParams,
+        dist_calc: Box<dyn DistanceCalculator>,
+    ) -> Result<Self, TWError> {
+        Self::create_with_encoding(path, fixed_params, dist_calc, Encoding::F32, false)
     }
-    pub fn open(path: &str) -> Result<Self, TWError> {
-        let (sm, header) = match StorageManager::open(Path::new(path)) {
-            Ok(ok) => ok,
-            Err(e) => return Err(TWError::SMError(e)),
+
+    /// like [`TinyWorld::create`], but vectors get stored with `encoding`, if
+    /// `keep_raw` is set the full precision vectors also get kept (in
+    /// `<path>.raw.tw`) and used to re-rank search results. `Binary` has to keep
+    /// them, hamming distances are only good enough to find candidates
+    pub fn create_with_encoding(
+        path: &str,
+        fixed_params: FixedParams,
+        dist_calc: Box<dyn DistanceCalculator>,
+        encoding: Encoding,
+        keep_raw: bool,
+    ) -> Result<Self, TWError> {
+        let (m_max, m0_max, m) = match (
+            u8::try_from(fixed_params.m_max),
+            u8::try_from(fixed_params.m0_max),
+            u8::try_from(fixed_params.m),
+        ) {
+            (Ok(m_max), Ok(m0_max), Ok(m)) => (m_max, m0_max, m),
+            _ => return Err(TWError::ParamsError),
         };
+        if matches!(encoding, Encoding::Binary) && !keep_raw {
+            return Err(TWError::ParamsError);
+        }
+
+        let dim = fixed_params.dimension;
+        let header = Header {
+            raw_vectors: keep_raw as u8,
+            m_max,
+            m0_max,
+            m,
+            m_l: fixed_params.level_norm,
+            ef_construction: fixed_params.ef_construction,
+            neighbor_heuristic: fixed_params.neighbor_heuristic as u8,
+            extend_candidates: fixed_params.extend_candidates as u8,
+            keep_pruned_connections: fixed_params.keep_pruned_connections as u8,
+            ..Header::new(
+                dim,
+                encoding.vec_size(dim as usize) as u32,
+                encoding.id(),
+                dist_calc.id(),
+            )
+        };
+        let (sm, header) = StorageManager::create(Path::new(path), header, &encoding.to_bytes())?;
+
+        let raw = match keep_raw {
+            true => {
+                let raw_header = Header {
+                    vec_size: dim * 4,
+                    encoding: Encoding::F32.id(),
+                    raw_vectors: 0,
+                    // same slots per page so the ids line up
+                    vec_page_slots: header.vec_page_slots,
+                    ..header
+                };
+                let (raw_sm, raw_header) =
+                    StorageManager::create(&Self::raw_path(Path::new(path)), raw_header, &[])?;
+                Some((raw_sm, raw_header))
+            }
+            false => None,
+        };
+
+        Self::from_parts(path, sm, header, raw, fixed_params, dist_calc, encoding)
+    }
+
+    pub fn open(path: &str, dist_calc: Box<dyn DistanceCalculator>) -> Result<Self, TWError> {
+        let (sm, header, encoder) = StorageManager::open(Path::new(path))?;
+        // stored distances (and how the file was built) only make sense with the
+        // same metric
+        if header.dist_id != dist_calc.id() {
+            return Err(TWError::MetricMismatchError);
+        }
+        let encoding = Encoding::from_bytes(header.encoding, header.dim as usize, &encoder)?;
+
+        let raw = match header.raw_vectors {
+            // see create_with_encoding
+            0 if matches!(encoding, Encoding::Binary) => return Err(TWError::ParamsError),
+            0 => None,
+            _ => {
+                let (raw_sm, raw_header, _) =
+                    StorageManager::open(&Self::raw_path(Path::new(path)))?;
+                Some((raw_sm, raw_header))
+            }
+        };
+
+        let fixed_params = FixedParams {
+            dimension: header.dim,
+            m: header.m as u32,
+            m_max: header.m_max as u32,
+            m0_max: header.m0_max as u32,
+            level_norm: header.m_l,
+            ef_construction: header.ef_construction,
+            neighbor_heuristic: header.neighbor_heuristic != 0,
+            extend_candidates: header.extend_candidates != 0,
+            keep_pruned_connections: header.keep_pruned_connections != 0,
+            // only the in memory hnsw repairs on insert
+            repair_on_insert: false,
+        };
+
+        Self::from_parts(path, sm, header, raw, fixed_params, dist_calc, encoding)
+    }
+
+    fn raw_path(path: &Path) -> PathBuf {
+        path.with_extension("raw.tw")
+    }
+
+    fn from_parts(
+        path: &str,
+        mut storage_manager: StorageManager,
+        header: Header,
+        raw: Option<(StorageManager, Header)>,
+        fixed_params: FixedParams,
+        dist_calc: Box<dyn DistanceCalculator>,
+        encoding: Encoding,
+    ) -> Result<Self, TWError> {
+        let vector_pool = VectorPool::for_file(&mut storage_manager, &header)?;
+        let index = Index::from_bytes(&storage_manager.read_index()?)?;
+        // the entry point is always on the top level
+        let entry = match index.num_levels() {
+            0 => None,
+            n => Some((header.ep, n - 1)),
+        };
+
+        let raw = match raw {
+            Some((mut raw_sm, raw_header)) => {
+                let vector_pool = VectorPool::for_file(&mut raw_sm, &raw_header)?;
+                Some(RawVectors {
+                    header: raw_header,
+                    storage_manager: raw_sm,
+                    vector_pool,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            entry,
+            rng: rand::thread_rng(),
+            fixed_params,
+            dist_calc,
+            encode_buff: vec![0; header.vec_size as usize],
+            prepared: PreparedQuery::default(),
+            header,
+            storage_manager,
+            vector_pool,
+            index,
+            encoding,
+            raw,
+            oversample: 4,
+        })
+    }
+
+    /// sets how many times `top_k` candidates get pulled from the (encoded)
+    /// graph before re-ranking them with the full precision vectors
+    pub fn set_oversample(&mut self, oversample: usize) {
+        self.oversample = usize::max(1, oversample);
+    }
+
+    pub fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(ItemId, f32)>, TWError> {
+        if query.len() != self.fixed_params.dimension as usize {
+            return Err(TWError::EmbSizeError);
+        }
+
+        self.prepare_query(query);
+        match self.entry {
+            Some((mut entry_id, entry_level)) => {
+                for level in (1..=entry_level).rev() {
+                    entry_id = self.search_layer(query, entry_id, 1, level)?[0].0;
+                }
+
+                let ef = match self.raw {
+                    Some(_) => usize::max(ef, top_k * self.oversample),
+                    None => usize::max(ef, top_k),
+                };
+                let mut out = self.search_layer(query, entry_id, ef, 0)?;
+                if let Some(raw) = &mut self.raw {
+                    for (id, dist) in out.iter_mut() {
+                        let raw_data = raw.vector_pool.get(*id, &mut raw.storage_manager)?;
+                        *dist = self.dist_calc.calc_dist(query, raw_data);
+                    }
+                }
+                out.sort_by(|a, b| a.1.total_cmp(&b.1));
+                out.truncate(top_k);
+                Ok(out)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, TWError> {
+        if new_data.len() != self.fixed_params.dimension as usize {
+            return Err(TWError::EmbSizeError);
+        }
+
+        let new_level = random_level(&mut self.rng, self.fixed_params.level_norm) as usize;
+        self.prepare_query(new_data);
+        self.encoding.encode(new_data, &mut self.encode_buff);
+        let new_id = self
+            .vector_pool
+            .push_bytes(&self.encode_buff, &mut self.storage_manager)?;
+        if let Some(raw) = &mut self.raw {
+            raw.vector_pool
+                .put_bytes(new_id, new_data.as_bytes(), &mut raw.storage_manager)?;
+        }
+        self.index.push_item(new_id, new_level);
+
+        match self.entry {
+            None => {
+                self.entry = Some((new_id, new_level));
+            }
+            Some((mut entry_id, entry_level)) => {
+                // find entry for new level
+                for level in (new_level + 1..=entry_level).rev() {
+                    entry_id = self.search_layer(new_data, entry_id, 1, level)?[0].0;
+                }
+
+                // insert node at each level for the rest of the way down
+                for level in (0..=usize::min(new_level, entry_level)).rev() {
+                    let top_ef_construction = self.search_layer(
+                        new_data,
+                        entry_id,
+                        self.fixed_params.ef_construction as usize,
+                        level,
+                    )?;
+                    let closest = top_ef_construction
+                        .iter()
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .unwrap()
+                        .0;
+                    let selected_neighbors = self.select_neighbors(
+                        new_id,
+                        new_data,
+                        top_ef_construction,
+                        self.fixed_params.m as usize,
+                        level,
+                    )?;
+
+                    let m_max = match level {
+                        0 => self.fixed_params.m0_max as usize,
+                        _ => self.fixed_params.m_max as usize,
+                    };
+                    for neighbor in selected_neighbors {
+                        self.index
+                            .push_conn(new_id, neighbor.0, neighbor.1, level)?;
+
+                        let n_conns = self.index.get_conns(neighbor.0, level)?;
+                        if n_conns.len() > m_max {
+                            let cands = n_conns
+                                .iter()
+                                .map(|c| (c.other, c.dist))
+                                .collect::<Vec<_>>();
+                            let n_data = self.get(neighbor.0)?;
+                            let selected = self
+                                .select_neighbors(neighbor.0, &n_data, cands, m_max, level)?
+                                .into_iter()
+                                .map(|(other, dist)| Conn { other, dist })
+                                .collect();
+                            self.index.set_conns(neighbor.0, level, selected)?;
+                        }
+                    }
+
+                    entry_id = closest;
+                }
+
+                if new_level > entry_level {
+                    self.entry = Some((new_id, new_level));
+                }
+            }
+        }
+
+        Ok(new_id)
+    }
+
+    pub fn delete(&mut self, id: ItemId) -> Result<(), TWError> {
+        self.vector_pool.delete(id, &mut self.storage_manager)?;
+        if let Some(raw) = &mut self.raw {
+            raw.vector_pool.delete(id, &mut raw.storage_manager)?;
+        }
+        self.index.remove_item(id);
+
+        if self.entry.is_some_and(|(ep, _)| ep == id) {
+            self.entry = self.index.top();
+        }
+
+        Ok(())
+    }
+
+    /// packs the live vectors into as few pages as possible and shrinks the file,
+    /// ids of anything that moved get rewritten in the index. returns the moves
+    /// so callers holding on to ids can follow along
+    pub fn compact(&mut self) -> Result<HashMap<ItemId, ItemId>, TWError> {
+        // same live slots and same slots per page means both files move the
+        // same way, so check that before either of them moves anything
+        if let Some(raw) = &mut self.raw {
+            let raw_live = raw.vector_pool.live(&mut raw.storage_manager)?;
+            if raw_live != self.vector_pool.live(&mut self.storage_manager)? {
+                return Err(TWError::RawMismatchError);
+            }
+        }
+
+        let mut moved = HashMap::new();
+        let compacted = self
+            .vector_pool
+            .compact(&mut self.storage_manager, &mut moved);
+        // anything that moved before an error still has to be followed
+        self.index.remap(&moved);
+        if let Some((ep, level)) = self.entry {
+            if let Some(new_ep) = moved.get(&ep) {
+                self.entry = Some((*new_ep, level));
+            }
+        }
+        compacted?;
+
+        if let Some(raw) = &mut self.raw {
+            let mut raw_moved = HashMap::new();
+            raw.vector_pool
+                .compact(&mut raw.storage_manager, &mut raw_moved)?;
+            if raw_moved != moved {
+                return Err(TWError::RawMismatchError);
+            }
+        }
+
+        self.flush()?;
+        Ok(moved)
+    }
+
+    /// writes all dirty vector pages, the index and the header out to the file
+    pub fn flush(&mut self) -> Result<(), TWError> {
+        self.vector_pool.flush(&mut self.storage_manager)?;
+        self.storage_manager
+            .write_tail(self.vector_pool.free_space(), &self.index.to_bytes())?;
+        if let Some((ep, _)) = self.entry {
+            self.header.ep = ep;
+        }
+        self.storage_manager.write_header(&mut self.header)?;
+        if let Some(raw) = &mut self.raw {
+            raw.vector_pool.flush(&mut raw.storage_manager)?;
+            raw.storage_manager
+                .write_tail(raw.vector_pool.free_space(), &[])?;
+            raw.storage_manager.write_header(&mut raw.header)?;
+        }
+        Ok(())
+    }
+
+    /// the stored vector, from the raw file if there is one, otherwise decoded
+    /// (so lossy for anything but `F32`)
+    pub fn get(&mut self, id: ItemId) -> Result<Vec<f32>, TWError> {
+        if let Some(raw) = &mut self.raw {
+            return Ok(raw.vector_pool.get(id, &mut raw.storage_manager)?.to_vec());
+        }
+        let data = self.vector_pool.get_bytes(id, &mut self.storage_manager)?;
+        let mut out = vec![0.0; self.fixed_params.dimension as usize];
+        self.encoding.decode(data, &mut out);
+        Ok(out)
+    }
+
+    /// node counts, degrees and anything wrong with the graph, for a saved
+    /// file just [`TinyWorld::open`] it first
+    pub fn stats(&self) -> GraphStats<ItemId> {
+        graph_stats::inspect(
+            &self.index.neighbor_lists(),
+            self.entry,
+            self.fixed_params.m0_max as usize,
+            self.fixed_params.m_max as usize,
+        )
+    }
+
+    /// all the problems [`TinyWorld::stats`] finds, if there are any
+    pub fn check(&self) -> Result<(), Vec<GraphIssue<ItemId>>> {
+        let stats = self.stats();
+        match stats.is_healthy() {
+            true => Ok(()),
+            false => Err(stats.issues),
+        }
+    }
+
+    pub fn fixed_params(&self) -> &FixedParams {
+        &self.fixed_params
+    }
+
+    pub fn len(&self) -> usize {
+        self.vector_pool.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// flushes, and copies the file (and the raw file) to `path` if thats
+    /// somewhere else. the copy is a full `.tw` file that can be opened on its own
+    pub fn save(&mut self, path: &Path) -> Result<(), TWError> {
+        self.flush()?;
+        if path == self.path {
+            return Ok(());
+        }
+        fs::copy(&self.path, path)?;
+        if self.raw.is_some() {
+            fs::copy(Self::raw_path(&self.path), Self::raw_path(path))?;
+        }
+        Ok(())
+    }
+
+    fn search_layer(
+        &mut self,
+        query: &[f32],
+        entry: ItemId,
+        top_k: usize,
+        level: usize,
+    ) -> Result<Vec<(ItemId, f32)>, TWError> {
+        let mut candidates = BinaryHeap::<MinDist<ItemId>>::new();
+        let mut found = BinaryHeap::<MaxDist<ItemId>>::new();
+        let mut visited = HashSet::new();
+
+        {
+            let dist = self.calc_dist(query, entry)?;
+            visited.insert(entry);
+            candidates.push(MinDist(Reverse(MaxDist { id: entry, dist })));
+            found.push(MaxDist { dist, id: entry });
+        }
+
+        while let Some(c) = candidates.pop() {
+            let f = found.peek().unwrap();
+
+            if c.0 .0.dist > f.dist {
+                break;
+            }
+
+            let c_nbs = self.index.get_conns(c.0 .0.id, level)?.to_vec();
+            for nb in c_nbs {
+                if visited.insert(nb.other) {
+                    let dist = self.calc_dist(query, nb.other)?;
+
+                    let f = found.peek().unwrap();
+
+                    if dist < f.dist || found.len() < top_k {
+                        candidates.push(MinDist(Reverse(MaxDist { dist, id: nb.other })));
+                        found.push(MaxDist { dist, id: nb.other });
+
+                        if found.len() > top_k {
+                            found.pop();
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(found.into_iter().map(|f| (f.id, f.dist)).collect())
+    }
+
+    /// sets up anything the encoding needs per query, has to be called before
+    /// [`TinyWorld::calc_dist`] gets used with a new query
+    fn prepare_query(&mut self, query: &[f32]) {
+        self.encoding
+            .prepare_query(query, self.dist_calc.as_mut(), &mut self.prepared);
+    }
+
+    /// distance from a full precision query to a stored vector, in whatever
+    /// encoding it's stored in
+    fn calc_dist(&mut self, query: &[f32], id: ItemId) -> Result<f32, TWError> {
+        let data = self.vector_pool.get_bytes(id, &mut self.storage_manager)?;
+        Ok(self
+            .encoding
+            .calc_dist(query, &self.prepared, data, self.dist_calc.as_mut()))
+    }
+
+    /// picks up to `top_k` neighbors for `id` (with vector `query`) out of
+    /// `candidates`, closest first or with the heuristic just like
+    /// [`crate::hnsw::HNSW`]. the heuristic compares candidates with each other,
+    /// so that goes through [`TinyWorld::get`] instead of the stored codes
+    fn select_neighbors(
+        &mut self,
+        id: ItemId,
+        query: &[f32],
+        candidates: Vec<(ItemId, f32)>,
+        top_k: usize,
+        level: usize,
+    ) -> Result<Vec<(ItemId, f32)>, TWError> {
+        let mut cands = candidates;
+        if !self.fixed_params.neighbor_heuristic {
+            cands.sort_by(|a, b| a.1.total_cmp(&b.1));
+            cands.truncate(top_k);
+            return Ok(cands);
+        }
+
+        // everything we've had to read in for this
+        let mut vectors = HashMap::new();
+
+        if self.fixed_params.extend_candidates {
+            let mut seen: HashSet<ItemId> = cands.iter().map(|c| c.0).collect();
+            seen.insert(id);
+            for i in 0..cands.len() {
+                let c_nbs = match self.index.get_conns(cands[i].0, level) {
+                    Ok(conns) => conns.iter().map(|c| c.other).collect::<Vec<_>>(),
+                    Err(_) => continue,
+                };
+                for c_nb in c_nbs {
+                    if seen.insert(c_nb) {
+                        let c_nb_data = self.get(c_nb)?;
+                        cands.push((c_nb, self.dist_calc.calc_dist(query, &c_nb_data)));
+                        vectors.insert(c_nb, c_nb_data);
+                    }
+                }
+            }
+        }
+
+        cands.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut out: Vec<(ItemId, f32)> = Vec::with_capacity(top_k);
+        let mut discarded = vec![];
+        for cand in cands {
+            if out.len() >= top_k {
+                break;
+            }
+            if let Entry::Vacant(e) = vectors.entry(cand.0) {
+                e.insert(self.get(cand.0)?);
+            }
+            let cand_data = &vectors[&cand.0];
+            let diverse = out
+                .iter()
+                .all(|o| cand.1 < self.dist_calc.calc_dist(cand_data, &vectors[&o.0]));
+            match diverse {
+                true => out.push(cand),
+                false => discarded.push(cand),
+            }
+        }
+
+        if self.fixed_params.keep_pruned_connections {
+            // these are already closest first
+            let room = top_k - out.len();
+            out.extend(discarded.into_iter().take(room));
+        }
 
-        Self {}
+        Ok(out)
     }
 }
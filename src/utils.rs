@@ -2,30 +2,30 @@ use std::cmp;
 
 use uuid::Uuid;
 
-pub struct MaxDist {
+pub struct MaxDist<I = Uuid> {
     pub dist: f32,
-    pub id: Uuid,
+    pub id: I,
 }
 
-impl Ord for MaxDist {
+impl<I: PartialEq> Ord for MaxDist<I> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.dist.total_cmp(&other.dist)
     }
 }
 
-impl PartialOrd for MaxDist {
+impl<I: PartialEq> PartialOrd for MaxDist<I> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.dist.total_cmp(&other.dist))
+        Some(self.cmp(other))
     }
 }
 
-impl Eq for MaxDist {}
+impl<I: PartialEq> Eq for MaxDist<I> {}
 
-impl PartialEq for MaxDist {
+impl<I: PartialEq> PartialEq for MaxDist<I> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
     }
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
-pub struct MinDist(pub cmp::Reverse<MaxDist>);
+pub struct MinDist<I: PartialEq = Uuid>(pub cmp::Reverse<MaxDist<I>>);
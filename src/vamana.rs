@@ -0,0 +1,491 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use zerocopy::{FromBytes, IntoBytes, TryFromBytes};
+
+use crate::{
+    distance_calculators::DistanceCalculator,
+    quantization::{Encoding, EncodingError, PreparedQuery},
+    storage_manager::{Header, ItemId, StorageManager, StorageManagerError},
+    vector_pool::{VectorPool, VectorPoolError},
+};
+
+/// diskann style single level graph. every node is one record in a vector page,
+/// the full vector followed by its neighbor list, so expanding a node during a
+/// search is a single page read. the compressed vectors of every node stay in
+/// memory and are what the search navigates by, the full vectors that come
+/// along with the pages are what results get ranked by
+pub struct Vamana {
+    path: PathBuf,
+    params: VamanaParams,
+    dist_calc: Box<dyn DistanceCalculator>,
+    header: Header,
+    storage_manager: StorageManager,
+    vector_pool: VectorPool,
+    entry: Option<ItemId>,
+    encoding: Encoding,
+    codes: HashMap<ItemId, Vec<u8>>,
+    // deleted nodes stay in the graph so it stays connected, they just don't
+    // show up in results
+    // TODO: consolidate these out every once in a while
+    deleted: HashSet<ItemId>,
+    prepared: PreparedQuery,
+}
+
+pub struct VamanaParams {
+    pub dimension: u32,
+    // max out degree
+    pub r: u32,
+    // search list size while building
+    pub l_build: u32,
+    // how much closer a neighbor has to be to prune another one, 1.0 is the
+    // plain relative neighborhood graph, anything above keeps longer edges
+    pub alpha: f32,
+}
+
+impl Default for VamanaParams {
+    fn default() -> Self {
+        Self {
+            dimension: 0,
+            r: 64,
+            l_build: 100,
+            alpha: 1.2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VamanaError {
+    SMError(StorageManagerError),
+    VPError(VectorPoolError),
+    EncodingError(EncodingError),
+    EmbSizeError,
+    ParamsError,
+    // the file was built with a different distance calculator
+    MetricMismatchError,
+    InvalidId,
+    DecodeError,
+    IoError(io::Error),
+}
+
+impl From<StorageManagerError> for VamanaError {
+    fn from(e: StorageManagerError) -> Self {
+        Self::SMError(e)
+    }
+}
+
+impl From<VectorPoolError> for VamanaError {
+    fn from(e: VectorPoolError) -> Self {
+        Self::VPError(e)
+    }
+}
+
+impl From<EncodingError> for VamanaError {
+    fn from(e: EncodingError) -> Self {
+        Self::EncodingError(e)
+    }
+}
+
+impl From<io::Error> for VamanaError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+// a node we've read the page of, with its exact distance
+struct Visited {
+    id: ItemId,
+    dist: f32,
+    vector: Vec<f32>,
+}
+
+impl Vamana {
+    /// `encoding` is for the in memory copies, the records on disk are always
+    /// full precision
+    pub fn create(
+        path: &str,
+        params: VamanaParams,
+        dist_calc: Box<dyn DistanceCalculator>,
+        encoding: Encoding,
+    ) -> Result<Self, VamanaError> {
+        // both end up in the header as u8s
+        let (r, l_build) = match (u8::try_from(params.r), u8::try_from(params.l_build)) {
+            (Ok(r), Ok(l_build)) if r > 0 && l_build > 0 => (r, l_build),
+            _ => return Err(VamanaError::ParamsError),
+        };
+
+        let dim = params.dimension;
+        // the hnsw fields get reused, `m_max` is `r`, `m` is `l_build` and `m_l`
+        // is alpha
+        let header = Header {
+            m_max: r,
+            m: l_build,
+            m_l: params.alpha,
+            ..Header::new(
+                dim,
+                record_size(dim as usize, r as usize) as u32,
+                encoding.id(),
+                dist_calc.id(),
+            )
+        };
+        let (sm, header) = StorageManager::create(Path::new(path), header, &encoding.to_bytes())?;
+
+        Self::from_parts(
+            path,
+            sm,
+            header,
+            params,
+            dist_calc,
+            encoding,
+            HashMap::new(),
+            HashSet::new(),
+        )
+    }
+
+    pub fn open(path: &str, dist_calc: Box<dyn DistanceCalculator>) -> Result<Self, VamanaError> {
+        let (mut sm, header, encoder) = StorageManager::open(Path::new(path))?;
+        // the graph got pruned with it
+        if header.dist_id != dist_calc.id() {
+            return Err(VamanaError::MetricMismatchError);
+        }
+        let encoding = Encoding::from_bytes(header.encoding, header.dim as usize, &encoder)?;
+        let params = VamanaParams {
+            dimension: header.dim,
+            r: header.m_max as u32,
+            l_build: header.m as u32,
+            alpha: header.m_l,
+        };
+
+        let code_size = encoding.vec_size(header.dim as usize);
+        let (codes, deleted) = match Self::tail_from_bytes(&sm.read_index()?, code_size) {
+            Some(t) => t,
+            None => return Err(VamanaError::DecodeError),
+        };
+
+        Self::from_parts(
+            path, sm, header, params, dist_calc, encoding, codes, deleted,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        path: &str,
+        mut storage_manager: StorageManager,
+        header: Header,
+        params: VamanaParams,
+        dist_calc: Box<dyn DistanceCalculator>,
+        encoding: Encoding,
+        codes: HashMap<ItemId, Vec<u8>>,
+        deleted: HashSet<ItemId>,
+    ) -> Result<Self, VamanaError> {
+        let vector_pool = VectorPool::for_file(&mut storage_manager, &header)?;
+        let entry = match codes.is_empty() {
+            true => None,
+            false => Some(header.ep),
+        };
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            params,
+            dist_calc,
+            header,
+            storage_manager,
+            vector_pool,
+            entry,
+            encoding,
+            codes,
+            deleted,
+            prepared: PreparedQuery::default(),
+        })
+    }
+
+    /// beam search with a list of `ef` candidates, navigating on the compressed
+    /// vectors and ranking on the full ones from the pages it reads
+    pub fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(ItemId, f32)>, VamanaError> {
+        if query.len() != self.params.dimension as usize {
+            return Err(VamanaError::EmbSizeError);
+        }
+
+        let mut out = self
+            .greedy_search(query, usize::max(ef, top_k))?
+            .into_iter()
+            .filter(|v| !self.deleted.contains(&v.id))
+            .map(|v| (v.id, v.dist))
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| a.1.total_cmp(&b.1));
+        out.truncate(top_k);
+        Ok(out)
+    }
+
+    pub fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, VamanaError> {
+        let dim = self.params.dimension as usize;
+        if new_data.len() != dim {
+            return Err(VamanaError::EmbSizeError);
+        }
+
+        let mut code = vec![0; self.encoding.vec_size(dim)];
+        self.encoding.encode(new_data, &mut code);
+        let mut record = vec![0; self.header.vec_size as usize];
+        write_record(&mut record, new_data, &[]);
+        let new_id = self
+            .vector_pool
+            .push_bytes(&record, &mut self.storage_manager)?;
+
+        if self.entry.is_none() {
+            self.codes.insert(new_id, code);
+            self.entry = Some(new_id);
+            return Ok(new_id);
+        }
+
+        // the new node isn't linked in yet so it can't turn up in its own search
+        let visited = self.greedy_search(new_data, self.params.l_build as usize)?;
+        self.codes.insert(new_id, code);
+        let neighbors = self.robust_prune(visited);
+        let neighbor_ids = neighbors.iter().map(|n| n.id).collect::<Vec<_>>();
+        self.set_neighbors(new_id, &neighbor_ids)?;
+
+        // back edges, pruning anyone that ends up over `r`
+        for neighbor in neighbors {
+            let mut n_neighbors = self.neighbors(neighbor.id)?;
+            if n_neighbors.contains(&new_id) {
+                continue;
+            }
+            n_neighbors.push(new_id);
+            if n_neighbors.len() > self.params.r as usize {
+                let mut cands = Vec::with_capacity(n_neighbors.len());
+                for id in n_neighbors {
+                    let vector = self.vector(id)?;
+                    let dist = self.dist_calc.calc_dist(&neighbor.vector, &vector);
+                    cands.push(Visited { id, dist, vector });
+                }
+                n_neighbors = self.robust_prune(cands).into_iter().map(|c| c.id).collect();
+            }
+            self.set_neighbors(neighbor.id, &n_neighbors)?;
+        }
+
+        Ok(new_id)
+    }
+
+    /// marks `id` as deleted, it stays in the graph for getting around but
+    /// doesn't come back from searches anymore
+    pub fn delete(&mut self, id: ItemId) -> Result<(), VamanaError> {
+        if !self.codes.contains_key(&id) || !self.deleted.insert(id) {
+            return Err(VamanaError::InvalidId);
+        }
+        Ok(())
+    }
+
+    pub fn get(&mut self, id: ItemId) -> Result<Vec<f32>, VamanaError> {
+        if self.deleted.contains(&id) {
+            return Err(VamanaError::InvalidId);
+        }
+        self.vector(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len() - self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// writes the dirty pages, the compressed vectors and the header out
+    pub fn flush(&mut self) -> Result<(), VamanaError> {
+        self.vector_pool.flush(&mut self.storage_manager)?;
+        self.storage_manager
+            .write_tail(self.vector_pool.free_space(), &self.tail_to_bytes())?;
+        if let Some(ep) = self.entry {
+            self.header.ep = ep;
+        }
+        self.storage_manager.write_header(&mut self.header)?;
+        Ok(())
+    }
+
+    /// flushes, and copies the file to `path` if thats somewhere else
+    pub fn save(&mut self, path: &Path) -> Result<(), VamanaError> {
+        self.flush()?;
+        if path != self.path {
+            fs::copy(&self.path, path)?;
+        }
+        Ok(())
+    }
+
+    /// the search list starts at the entry point and keeps the `l` closest (by
+    /// compressed distance) nodes seen so far, the closest one that hasn't been
+    /// expanded yet gets its page read until there's none left. returns every
+    /// node that got expanded
+    fn greedy_search(&mut self, query: &[f32], l: usize) -> Result<Vec<Visited>, VamanaError> {
+        let entry = match self.entry {
+            Some(e) => e,
+            None => return Ok(vec![]),
+        };
+        self.encoding
+            .prepare_query(query, self.dist_calc.as_mut(), &mut self.prepared);
+
+        // (approx dist, id, expanded), sorted closest first
+        let mut list = vec![(self.approx_dist(query, entry), entry, false)];
+        let mut seen = HashSet::from([entry]);
+        let mut visited = vec![];
+
+        while let Some(next) = list.iter_mut().find(|c| !c.2) {
+            next.2 = true;
+            let id = next.1;
+
+            let record = self.vector_pool.get_bytes(id, &mut self.storage_manager)?;
+            let (vector, neighbors) = read_record(record, self.params.dimension as usize);
+            visited.push(Visited {
+                id,
+                dist: self.dist_calc.calc_dist(query, vector),
+                vector: vector.to_vec(),
+            });
+
+            for n in neighbors {
+                if seen.insert(n) {
+                    let dist = self.approx_dist(query, n);
+                    let pos = list.partition_point(|c| c.0 <= dist);
+                    list.insert(pos, (dist, n, false));
+                }
+            }
+            list.truncate(l);
+        }
+
+        Ok(visited)
+    }
+
+    /// picks up to `r` of `cands` (which have their distance to the node being
+    /// pruned for), closest first, dropping any candidate that's `alpha` times
+    /// closer to an already picked neighbor than to the node
+    fn robust_prune(&mut self, mut cands: Vec<Visited>) -> Vec<Visited> {
+        cands.sort_by(|a, b| a.dist.total_cmp(&b.dist));
+        let mut out: Vec<Visited> = vec![];
+        for cand in cands {
+            if out.len() >= self.params.r as usize {
+                break;
+            }
+            let pruned = out.iter().any(|o| {
+                self.params.alpha * self.dist_calc.calc_dist(&o.vector, &cand.vector) <= cand.dist
+            });
+            if !pruned {
+                out.push(cand);
+            }
+        }
+        out
+    }
+
+    fn approx_dist(&mut self, query: &[f32], id: ItemId) -> f32 {
+        match self.codes.get(&id) {
+            Some(code) => {
+                self.encoding
+                    .calc_dist(query, &self.prepared, code, self.dist_calc.as_mut())
+            }
+            // an edge to a node we don't know about, nothing to do but skip it
+            None => f32::MAX,
+        }
+    }
+
+    fn vector(&mut self, id: ItemId) -> Result<Vec<f32>, VamanaError> {
+        let record = self.vector_pool.get_bytes(id, &mut self.storage_manager)?;
+        Ok(read_record(record, self.params.dimension as usize)
+            .0
+            .to_vec())
+    }
+
+    fn neighbors(&mut self, id: ItemId) -> Result<Vec<ItemId>, VamanaError> {
+        let record = self.vector_pool.get_bytes(id, &mut self.storage_manager)?;
+        Ok(read_record(record, self.params.dimension as usize).1)
+    }
+
+    fn set_neighbors(&mut self, id: ItemId, neighbors: &[ItemId]) -> Result<(), VamanaError> {
+        let dim = self.params.dimension as usize;
+        let record = self
+            .vector_pool
+            .get_bytes_mut(id, &mut self.storage_manager)?;
+        let (_, list) = record.split_at_mut(dim * 4);
+        write_neighbors(list, neighbors);
+        Ok(())
+    }
+
+    // the compressed vectors as a count and then (id, code) pairs, then the
+    // deleted ids as a count and the ids
+    fn tail_to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from((self.codes.len() as u32).as_bytes());
+        for (id, code) in &self.codes {
+            out.extend_from_slice(id.as_bytes());
+            out.extend_from_slice(code);
+        }
+        out.extend_from_slice((self.deleted.len() as u32).as_bytes());
+        for id in &self.deleted {
+            out.extend_from_slice(id.as_bytes());
+        }
+        out
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn tail_from_bytes(
+        bytes: &[u8],
+        code_size: usize,
+    ) -> Option<(HashMap<ItemId, Vec<u8>>, HashSet<ItemId>)> {
+        let mut codes = HashMap::new();
+        let mut deleted = HashSet::new();
+        if bytes.is_empty() {
+            return Some((codes, deleted));
+        }
+
+        let (n, mut rdr) = u32::read_from_prefix(bytes).ok()?;
+        for _ in 0..n {
+            let (id, rest) = ItemId::try_read_from_prefix(rdr).ok()?;
+            let (code, rest) = rest.split_at_checked(code_size)?;
+            codes.insert(id, code.to_vec());
+            rdr = rest;
+        }
+        let (n, mut rdr) = u32::read_from_prefix(rdr).ok()?;
+        for _ in 0..n {
+            let (id, rest) = ItemId::try_read_from_prefix(rdr).ok()?;
+            deleted.insert(id);
+            rdr = rest;
+        }
+
+        match rdr.is_empty() {
+            true => Some((codes, deleted)),
+            false => None,
+        }
+    }
+}
+
+/// a record is the vector, the neighbor count and then room for `r` neighbors
+fn record_size(dim: usize, r: usize) -> usize {
+    (dim * 4) + 4 + (r * size_of::<ItemId>())
+}
+
+fn write_record(record: &mut [u8], vector: &[f32], neighbors: &[ItemId]) {
+    let (v, list) = record.split_at_mut(vector.len() * 4);
+    v.copy_from_slice(vector.as_bytes());
+    write_neighbors(list, neighbors);
+}
+
+fn write_neighbors(list: &mut [u8], neighbors: &[ItemId]) {
+    let (count, ids) = list.split_at_mut(4);
+    count.copy_from_slice((neighbors.len() as u32).as_bytes());
+    let neighbors = neighbors.as_bytes();
+    ids[..neighbors.len()].copy_from_slice(neighbors);
+}
+
+fn read_record(record: &[u8], dim: usize) -> (&[f32], Vec<ItemId>) {
+    let (v, list) = record.split_at(dim * 4);
+    let (count, ids) = u32::read_from_prefix(list).unwrap();
+    let neighbors = ids
+        .chunks_exact(size_of::<ItemId>())
+        .take(count as usize)
+        .map(|id| ItemId::try_read_from_bytes(id).unwrap())
+        .collect();
+    (<[f32]>::ref_from_bytes(v).unwrap(), neighbors)
+}
@@ -0,0 +1,241 @@
+use std::{cell::RefCell, path::Path};
+
+use uuid::Uuid;
+
+use crate::{
+    distance_calculators::DistanceCalculator,
+    flat::FlatIndex,
+    hnsw::{Error, FixedParams, HNSW},
+    ivf::{IVFError, IVF},
+    storage_manager::ItemId,
+    tinyworld::{TWError, TinyWorld},
+    vamana::{Vamana, VamanaError},
+};
+
+/// what every index can do, so the backend can be picked at runtime. `Id` is
+/// whatever the backend hands out for inserted vectors ([`Uuid`] for the in
+/// memory ones, [`ItemId`] for [`TinyWorld`])
+pub trait VectorIndex<Id> {
+    type Error;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<Id, Self::Error>;
+
+    /// stops at the first error, anything before it stays inserted
+    fn insert_batch(&mut self, new_data: &[&[f32]]) -> Result<Vec<Id>, Self::Error> {
+        new_data.iter().map(|d| self.insert(d)).collect()
+    }
+
+    // takes `&mut self` since the file backed index has to page vectors in
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(Id, f32)>, Self::Error>;
+
+    fn delete(&mut self, id: Id) -> Result<(), Self::Error>;
+
+    fn get(&mut self, id: Id) -> Result<Vec<f32>, Self::Error>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), Self::Error>;
+}
+
+impl VectorIndex<Uuid> for HNSW {
+    type Error = Error;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<Uuid, Error> {
+        HNSW::insert(self, new_data)
+    }
+
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(Uuid, f32)>, Error> {
+        HNSW::search(self, query, top_k, ef)
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        HNSW::delete(self, id)
+    }
+
+    fn get(&mut self, id: Uuid) -> Result<Vec<f32>, Error> {
+        match HNSW::get(self, id) {
+            Some(v) => Ok(v.to_vec()),
+            None => Err(Error::InvalidId),
+        }
+    }
+
+    fn len(&self) -> usize {
+        HNSW::len(self)
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), Error> {
+        HNSW::save(self, path)
+    }
+}
+
+impl VectorIndex<Uuid> for FlatIndex {
+    type Error = Error;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<Uuid, Error> {
+        FlatIndex::insert(self, new_data)
+    }
+
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(Uuid, f32)>, Error> {
+        FlatIndex::search(self, query, top_k, ef)
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        FlatIndex::delete(self, id)
+    }
+
+    fn get(&mut self, id: Uuid) -> Result<Vec<f32>, Error> {
+        match FlatIndex::get(self, id) {
+            Some(v) => Ok(v.to_vec()),
+            None => Err(Error::InvalidId),
+        }
+    }
+
+    fn len(&self) -> usize {
+        FlatIndex::len(self)
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), Error> {
+        FlatIndex::save(self, path)
+    }
+}
+
+impl VectorIndex<ItemId> for TinyWorld {
+    type Error = TWError;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, TWError> {
+        TinyWorld::insert(self, new_data)
+    }
+
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(ItemId, f32)>, TWError> {
+        TinyWorld::search(self, query, top_k, ef)
+    }
+
+    fn delete(&mut self, id: ItemId) -> Result<(), TWError> {
+        TinyWorld::delete(self, id)
+    }
+
+    fn get(&mut self, id: ItemId) -> Result<Vec<f32>, TWError> {
+        TinyWorld::get(self, id)
+    }
+
+    fn len(&self) -> usize {
+        TinyWorld::len(self)
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), TWError> {
+        TinyWorld::save(self, path)
+    }
+}
+
+// `ef` is `nprobe` here, the number of posting lists to look through
+impl VectorIndex<ItemId> for IVF {
+    type Error = IVFError;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, IVFError> {
+        IVF::insert(self, new_data)
+    }
+
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(ItemId, f32)>, IVFError> {
+        IVF::search(self, query, top_k, ef)
+    }
+
+    fn delete(&mut self, id: ItemId) -> Result<(), IVFError> {
+        IVF::delete(self, id)
+    }
+
+    fn get(&mut self, id: ItemId) -> Result<Vec<f32>, IVFError> {
+        IVF::get(self, id)
+    }
+
+    fn len(&self) -> usize {
+        IVF::len(self)
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), IVFError> {
+        IVF::save(self, path)
+    }
+}
+
+impl VectorIndex<ItemId> for Vamana {
+    type Error = VamanaError;
+
+    fn insert(&mut self, new_data: &[f32]) -> Result<ItemId, VamanaError> {
+        Vamana::insert(self, new_data)
+    }
+
+    fn search(
+        &mut self,
+        query: &[f32],
+        top_k: usize,
+        ef: usize,
+    ) -> Result<Vec<(ItemId, f32)>, VamanaError> {
+        Vamana::search(self, query, top_k, ef)
+    }
+
+    fn delete(&mut self, id: ItemId) -> Result<(), VamanaError> {
+        Vamana::delete(self, id)
+    }
+
+    fn get(&mut self, id: ItemId) -> Result<Vec<f32>, VamanaError> {
+        Vamana::get(self, id)
+    }
+
+    fn len(&self) -> usize {
+        Vamana::len(self)
+    }
+
+    fn save(&mut self, path: &Path) -> Result<(), VamanaError> {
+        Vamana::save(self, path)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Backend {
+    HNSW,
+    Flat,
+}
+
+/// only the in memory backends, [`TinyWorld`] needs a file to live in so it
+/// gets made with [`TinyWorld::create`]
+pub fn new_index(
+    backend: Backend,
+    fixed_params: FixedParams,
+    dist_calc: Box<dyn DistanceCalculator>,
+) -> Box<dyn VectorIndex<Uuid, Error = Error>> {
+    match backend {
+        Backend::HNSW => Box::new(HNSW::new(fixed_params, RefCell::new(dist_calc))),
+        Backend::Flat => Box::new(FlatIndex::new(
+            fixed_params.dimension,
+            RefCell::new(dist_calc),
+        )),
+    }
+}
@@ -1,200 +1,570 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io,
+};
 
-use zerocopy::{IntoBytes, TryFromBytes};
+use zerocopy::{FromBytes, IntoBytes};
 
-use crate::storage_manager::{ItemId, StorageManager};
+use crate::storage_manager::{vec_offset, Header, ItemId, StorageManager, StorageManagerError};
 
+// pages kept in memory per pool, the same for every backend
+// TODO: figure out how big this should actually be, maybe make it configurable
+pub const POOL_SIZE: usize = 256;
+
+#[derive(Debug)]
 pub enum VectorPoolError {
     InvalidItemId,
+    VecSizeError,
+    IoError(io::Error),
+}
+
+impl From<io::Error> for VectorPoolError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
 }
 
 pub struct VectorPool {
-    pool: Vec<u8>,
+    // backed by u32s so that every frame (and every vector in it) is aligned for f32
+    pool: Vec<u32>,
     page_to_frame_map: HashMap<u32, usize>,
+    frame_to_page: Vec<Option<u32>>,
     empty_frames: Vec<usize>,
     dirty_pages: HashSet<usize>,
+    // free slots in the pages we have loaded
     free_slots: Vec<ItemId>,
+    // free slot counts for every page, loaded or not, this gets persisted
+    free_space: Vec<u32>,
+    // pages that have any free slots
+    free_pages: BTreeSet<u32>,
     page_size: usize,
     vec_size: usize,
     slots_per_page: usize,
+    next_victim: usize,
 }
 
 impl VectorPool {
-    pub fn new(pool_size: usize, page_size: usize, vec_size: usize, slots_per_page: usize) -> Self {
-        let pool = vec![0; page_size * pool_size];
+    /// a pool over the pages of an opened (or just created) `.tw` file
+    pub fn for_file(
+        storage_manager: &mut StorageManager,
+        header: &Header,
+    ) -> Result<Self, StorageManagerError> {
+        let free_space = storage_manager.read_free_space_map()?;
+        Ok(Self::new(
+            POOL_SIZE,
+            storage_manager.page_size(),
+            header.vec_size as usize,
+            header.vec_page_slots as usize,
+            free_space,
+        ))
+    }
+
+    pub fn new(
+        pool_size: usize,
+        page_size: usize,
+        vec_size: usize,
+        slots_per_page: usize,
+        free_space: Vec<u32>,
+    ) -> Self {
+        assert!(page_size.is_multiple_of(4));
+        assert!(vec_offset(slots_per_page) + (slots_per_page * vec_size) <= page_size);
+
+        let pool = vec![0; (page_size / 4) * pool_size];
+        let frame_to_page = vec![None; pool_size];
         let empty_frames = Vec::from_iter(0..pool_size);
         let dirty_pages = HashSet::new();
         let free_slots = Vec::new();
         let page_to_frame_map = HashMap::new();
+        let free_pages = free_space
+            .iter()
+            .zip(0..)
+            .filter(|(free, _)| **free > 0)
+            .map(|(_, page_number)| page_number)
+            .collect();
 
         Self {
             pool,
+            frame_to_page,
             empty_frames,
             dirty_pages,
             free_slots,
+            free_space,
+            free_pages,
             page_size,
             vec_size,
             page_to_frame_map,
             slots_per_page,
+            next_victim: 0,
         }
     }
 
     pub fn get(&mut self, id: ItemId, sm: &mut StorageManager) -> Result<&[f32], VectorPoolError> {
-        if id.slot_number as usize >= self.slots_per_page {
+        let vec = self.get_bytes(id, sm)?;
+        Ok(<[f32]>::ref_from_bytes(vec).unwrap())
+    }
+
+    /// the raw bytes of a stored vector, whatever the encoding is
+    pub fn get_bytes(
+        &mut self,
+        id: ItemId,
+        sm: &mut StorageManager,
+    ) -> Result<&[u8], VectorPoolError> {
+        if id.slot_number as usize >= self.slots_per_page
+            || id.page_number as usize >= self.free_space.len()
+        {
             return Err(VectorPoolError::InvalidItemId);
         }
 
-        let page_number = id.page_number;
-        match self.page_to_frame_map.get(&page_number) {
-            Some(frame_number) => {
-                let page_start = frame_number * self.page_size;
-                let page_end = page_start + self.page_size;
-                let page = self.pool.get(page_start..page_end).unwrap();
-
-                let slot_start = id.slot_number as usize;
-                let slot_end = slot_start + 1;
-                let slot = u8::try_ref_from_bytes(page.get(slot_start..slot_end).unwrap()).unwrap();
-
-                match slot {
-                    0 => Err(VectorPoolError::InvalidItemId),
-                    1 => {
-                        let vec_start = self.slots_per_page as usize
-                            + (id.slot_number as usize * self.vec_size);
-                        let vec_end = vec_start + self.vec_size;
-
-                        Ok(
-                            <[f32]>::try_ref_from_bytes(page.get(vec_start..vec_end).unwrap())
-                                .unwrap(),
-                        )
-                    }
-                    _ => panic!(),
-                }
+        let frame_idx = self.load_page(id.page_number, sm)?;
+        let slots_per_page = self.slots_per_page;
+        let vec_size = self.vec_size;
+        let frame = self.frame(frame_idx);
+
+        match frame[id.slot_number as usize] {
+            0 => Err(VectorPoolError::InvalidItemId),
+            1 => {
+                let vec_start = vec_offset(slots_per_page) + (id.slot_number as usize * vec_size);
+                let vec_end = vec_start + vec_size;
+
+                Ok(frame.get(vec_start..vec_end).unwrap())
             }
-            None => match self.empty_frames.pop() {
-                Some(idx) => {
-                    let frame_start = self.page_size * idx;
-                    let frame_end = frame_start + self.page_size;
-                    let frame = self.pool.get_mut(frame_start..frame_end).unwrap();
-
-                    sm.read_page(id.page_number, frame);
-
-                    self.page_to_frame_map.insert(id.page_number, idx);
-                    let slots = frame.get(0..self.slots_per_page).unwrap();
-                    for (slot, slot_number) in slots.iter().zip(0..) {
-                        if *slot == 0 {
-                            self.free_slots.push(ItemId {
-                                page_number,
-                                slot_number,
-                            });
-                        }
-                    }
-
-                    let slot_start = id.slot_number as usize;
-                    let slot_end = slot_start + 1;
-                    let slot =
-                        u8::try_ref_from_bytes(frame.get(slot_start..slot_end).unwrap()).unwrap();
-
-                    match slot {
-                        0 => Err(VectorPoolError::InvalidItemId),
-                        1 => {
-                            let vec_start = self.slots_per_page as usize
-                                + (id.slot_number as usize * self.vec_size);
-                            let vec_end = vec_start + self.vec_size;
-
-                            Ok(
-                                <[f32]>::try_ref_from_bytes(frame.get(vec_start..vec_end).unwrap())
-                                    .unwrap(),
-                            )
-                        }
-                        _ => panic!(),
-                    }
-                }
-                None => todo!("implement algo for choosing frame to ditch"),
-            },
+            _ => panic!(),
         }
     }
 
-    pub fn push(
+    /// for changing a stored vector in place, its page gets marked dirty
+    pub fn get_bytes_mut(
         &mut self,
-        new: &[f32],
+        id: ItemId,
+        sm: &mut StorageManager,
+    ) -> Result<&mut [u8], VectorPoolError> {
+        // checks the id and pulls the page in
+        self.get_bytes(id, sm)?;
+
+        let frame_idx = self.load_page(id.page_number, sm)?;
+        self.dirty_pages.insert(frame_idx);
+        let vec_start = vec_offset(self.slots_per_page) + (id.slot_number as usize * self.vec_size);
+        let vec_end = vec_start + self.vec_size;
+        Ok(self
+            .frame_mut(frame_idx)
+            .get_mut(vec_start..vec_end)
+            .unwrap())
+    }
+
+    pub fn push_bytes(
+        &mut self,
+        new: &[u8],
         sm: &mut StorageManager,
     ) -> Result<ItemId, VectorPoolError> {
         // so first we check if any of our in memory pages has a free slot,
         // then we check if we have any free frames, if we dont, we ditch one,
         // if we write a new frame, either from ditching or not, we need to mark
         // the page as dirty, and add a page in the storage manager when we write
+        if new.len() != self.vec_size {
+            return Err(VectorPoolError::VecSizeError);
+        }
 
-        match self.free_slots.pop() {
-            Some(item_id) => {
-                let page_number = item_id.page_number;
-                let slot_number = item_id.slot_number;
-
-                let frame_idx = self.page_to_frame_map.get(&page_number).unwrap();
-                let frame_start = frame_idx * self.page_size;
-                let frame_end = frame_start + self.page_size;
-
-                let frame = self.pool.get_mut(frame_start..frame_end).unwrap();
-
-                let slot = u8::try_ref_from_bytes(
-                    frame
-                        .get(slot_number as usize..slot_number as usize + 1)
-                        .unwrap(),
-                )
-                .unwrap();
-                match slot {
-                    0 => {
-                        let vec_start =
-                            self.slots_per_page + (slot_number as usize * self.vec_size);
-                        let vec_end = vec_start + self.vec_size;
-                        let vec = frame.get_mut(vec_start..vec_end).unwrap();
-
-                        vec.copy_from_slice(new.as_bytes());
-                        self.dirty_pages.insert(*frame_idx);
-                    }
-                    _ => panic!(),
+        let item_id = match self.free_slots.pop() {
+            Some(item_id) => item_id,
+            None => match self.free_pages.first() {
+                // one of the pages on disk has room, pull it in
+                Some(page_number) => {
+                    self.load_page(*page_number, sm)?;
+                    self.free_slots.pop().unwrap()
+                }
+                None => {
+                    self.new_page(sm)?;
+                    self.free_slots.pop().unwrap()
                 }
+            },
+        };
+
+        self.write_slot(item_id, new, sm)?;
 
-                Ok(item_id)
+        Ok(item_id)
+    }
+
+    /// like [`VectorPool::push_bytes`], but only into one of `pages` (or a fresh
+    /// page if none of them have room), for keeping related vectors together
+    pub fn push_bytes_in(
+        &mut self,
+        pages: &[u32],
+        new: &[u8],
+        sm: &mut StorageManager,
+    ) -> Result<ItemId, VectorPoolError> {
+        if new.len() != self.vec_size {
+            return Err(VectorPoolError::VecSizeError);
+        }
+
+        let item_id = match pages
+            .iter()
+            .find(|p| self.free_space.get(**p as usize).is_some_and(|f| *f > 0))
+        {
+            Some(page_number) => {
+                self.load_page(*page_number, sm)?;
+                let pos = self
+                    .free_slots
+                    .iter()
+                    .rposition(|s| s.page_number == *page_number)
+                    .unwrap();
+                self.free_slots.remove(pos)
+            }
+            None => {
+                self.new_page(sm)?;
+                self.free_slots.pop().unwrap()
             }
-            None => match self.empty_frames.pop() {
-                Some(frame_idx) => {
-                    let frame_start = frame_idx * self.page_size;
-                    let frame_end = frame_start + self.page_size;
-
-                    let frame = self.pool.get_mut(frame_start..frame_end).unwrap();
-                    frame[0] = 1;
-
-                    let vec_start = self.slots_per_page;
-                    let vec_end = vec_start + self.vec_size;
-                    let vec = frame.get_mut(vec_start..vec_end).unwrap();
-
-                    vec.copy_from_slice(new.as_bytes());
-
-                    let page_number = sm.new_page();
-                    self.page_to_frame_map.insert(page_number, frame_idx);
-                    for slot_number in 1..self.slots_per_page {
-                        self.free_slots.push(ItemId {
-                            slot_number: slot_number as u32,
-                            page_number,
-                        })
-                    }
-
-                    Ok(ItemId {
-                        slot_number: 0,
+        };
+
+        self.write_slot(item_id, new, sm)?;
+
+        Ok(item_id)
+    }
+
+    /// calls `f` with every live vector in `page_number`
+    pub fn scan_page(
+        &mut self,
+        page_number: u32,
+        sm: &mut StorageManager,
+        mut f: impl FnMut(ItemId, &[u8]),
+    ) -> Result<(), VectorPoolError> {
+        if page_number as usize >= self.free_space.len() {
+            return Err(VectorPoolError::InvalidItemId);
+        }
+
+        let frame_idx = self.load_page(page_number, sm)?;
+        let slots_per_page = self.slots_per_page;
+        let vec_size = self.vec_size;
+        let frame = self.frame(frame_idx);
+        for slot_number in 0..slots_per_page {
+            if frame[slot_number] == 1 {
+                let vec_start = vec_offset(slots_per_page) + (slot_number * vec_size);
+                let id = ItemId {
+                    slot_number: slot_number as u32,
+                    page_number,
+                };
+                f(id, &frame[vec_start..vec_start + vec_size]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// writes `new` into the slot at `id` instead of wherever is free, this is
+    /// for keeping another pool's ids in lockstep, so `id` has to be free and
+    /// either in an existing page or the next new one
+    pub fn put_bytes(
+        &mut self,
+        id: ItemId,
+        new: &[u8],
+        sm: &mut StorageManager,
+    ) -> Result<(), VectorPoolError> {
+        if new.len() != self.vec_size {
+            return Err(VectorPoolError::VecSizeError);
+        }
+        if id.slot_number as usize >= self.slots_per_page
+            || id.page_number as usize > self.free_space.len()
+        {
+            return Err(VectorPoolError::InvalidItemId);
+        }
+
+        if id.page_number as usize == self.free_space.len() {
+            self.new_page(sm)?;
+        }
+        let frame_idx = self.load_page(id.page_number, sm)?;
+        if self.frame(frame_idx)[id.slot_number as usize] != 0 {
+            return Err(VectorPoolError::InvalidItemId);
+        }
+
+        self.free_slots.retain(|s| *s != id);
+        self.write_slot(id, new, sm)?;
+
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: ItemId, sm: &mut StorageManager) -> Result<(), VectorPoolError> {
+        if id.slot_number as usize >= self.slots_per_page
+            || id.page_number as usize >= self.free_space.len()
+        {
+            return Err(VectorPoolError::InvalidItemId);
+        }
+
+        let frame_idx = self.load_page(id.page_number, sm)?;
+        let frame = self.frame_mut(frame_idx);
+        match frame[id.slot_number as usize] {
+            0 => return Err(VectorPoolError::InvalidItemId),
+            1 => frame[id.slot_number as usize] = 0,
+            _ => panic!(),
+        }
+        self.dirty_pages.insert(frame_idx);
+        self.free_slots.push(id);
+        self.set_free_space(id.page_number, self.free_space[id.page_number as usize] + 1);
+
+        Ok(())
+    }
+
+    /// every live id, in file order
+    pub fn live(&mut self, sm: &mut StorageManager) -> Result<Vec<ItemId>, VectorPoolError> {
+        let mut live = vec![];
+        for page_number in 0..self.free_space.len() as u32 {
+            let frame_idx = self.load_page(page_number, sm)?;
+            let slots = self.frame(frame_idx).get(0..self.slots_per_page).unwrap();
+            for (slot, slot_number) in slots.iter().zip(0..) {
+                if *slot == 1 {
+                    live.push(ItemId {
                         page_number,
-                    })
+                        slot_number,
+                    });
                 }
-                None => todo!("ditch a slot and do the above in the free space"),
-            },
+            }
         }
+        Ok(live)
     }
 
-    pub fn flush(&self, sm: &mut StorageManager) {
-        todo!("write everything out to disk that needs to")
+    /// moves every live vector to the front of the file so that pages are full,
+    /// then drops the empty pages off the end. where everything that moved went
+    /// gets put in `moved` as it goes, so if this fails partway `moved` still
+    /// says where everything is
+    pub fn compact(
+        &mut self,
+        sm: &mut StorageManager,
+        moved: &mut HashMap<ItemId, ItemId>,
+    ) -> Result<(), VectorPoolError> {
+        let live = self.live(sm)?;
+
+        // live is sorted, so everything only ever moves towards the front, and
+        // anything sitting where we're moving to has already been moved out
+        let mut buff = vec![0; self.vec_size];
+        for (from, k) in live.iter().zip(0..) {
+            let to = ItemId {
+                page_number: (k / self.slots_per_page) as u32,
+                slot_number: (k % self.slots_per_page) as u32,
+            };
+            if to == *from {
+                continue;
+            }
+
+            if let Err(e) = self.move_slot(*from, to, &mut buff, sm) {
+                // the slots we filled might still be on the free list
+                self.refresh_free_slots();
+                return Err(e);
+            }
+            moved.insert(*from, to);
+        }
+
+        let num_pages = live.len().div_ceil(self.slots_per_page);
+        for page_number in num_pages as u32..self.free_space.len() as u32 {
+            if let Some(frame_idx) = self.page_to_frame_map.remove(&page_number) {
+                self.dirty_pages.remove(&frame_idx);
+                self.frame_to_page[frame_idx] = None;
+                self.empty_frames.push(frame_idx);
+            }
+            self.free_pages.remove(&page_number);
+        }
+        self.free_space.truncate(num_pages);
+        sm.truncate(num_pages as u32);
+
+        // start over with an empty pool so the free slots get picked up fresh
+        self.flush(sm)?;
+        self.page_to_frame_map.clear();
+        self.frame_to_page.fill(None);
+        self.empty_frames = Vec::from_iter(0..self.frame_to_page.len());
+        self.free_slots.clear();
+
+        Ok(())
+    }
+
+    /// writes every dirty page out to disk, the free space map still has to be
+    /// written with [`StorageManager::write_tail`]. pages that didn't make it
+    /// out stay dirty
+    pub fn flush(&mut self, sm: &mut StorageManager) -> io::Result<()> {
+        let mut dirty: Vec<usize> = self.dirty_pages.iter().copied().collect();
+        dirty.sort();
+        for frame_idx in dirty {
+            let page_number = self.frame_to_page[frame_idx].unwrap();
+            sm.write_page(page_number, self.frame(frame_idx))?;
+            self.dirty_pages.remove(&frame_idx);
+        }
+        Ok(())
+    }
+
+    pub fn free_space(&self) -> &[u32] {
+        &self.free_space
+    }
+
+    /// number of vectors stored, loaded or not
+    pub fn len(&self) -> usize {
+        let free: usize = self.free_space.iter().map(|f| *f as usize).sum();
+        (self.free_space.len() * self.slots_per_page) - free
+    }
+
+    /// writes `data` into the (empty) slot at `id`
+    fn write_slot(
+        &mut self,
+        id: ItemId,
+        data: &[u8],
+        sm: &mut StorageManager,
+    ) -> Result<(), VectorPoolError> {
+        let frame_idx = self.load_page(id.page_number, sm)?;
+        let slot_number = id.slot_number as usize;
+        let vec_start = vec_offset(self.slots_per_page) + (slot_number * self.vec_size);
+        let vec_end = vec_start + self.vec_size;
+        let frame = self.frame_mut(frame_idx);
+
+        match frame[slot_number] {
+            0 => {
+                frame[slot_number] = 1;
+                frame
+                    .get_mut(vec_start..vec_end)
+                    .unwrap()
+                    .copy_from_slice(data);
+            }
+            _ => panic!(),
+        }
+        self.dirty_pages.insert(frame_idx);
+        self.set_free_space(id.page_number, self.free_space[id.page_number as usize] - 1);
+        Ok(())
+    }
+
+    /// copies `from` into the (empty) slot at `to` and then frees `from`, the
+    /// copy goes first so nothing is lost if either of them fails
+    fn move_slot(
+        &mut self,
+        from: ItemId,
+        to: ItemId,
+        buff: &mut [u8],
+        sm: &mut StorageManager,
+    ) -> Result<(), VectorPoolError> {
+        buff.copy_from_slice(self.get_bytes(from, sm)?);
+        self.write_slot(to, buff, sm)?;
+        self.delete(from, sm)
+    }
+
+    /// rebuilds the free slot list from the pages that are loaded
+    fn refresh_free_slots(&mut self) {
+        self.free_slots.clear();
+        for (page_number, frame_idx) in self.page_to_frame_map.clone() {
+            let slots = self.frame(frame_idx).get(0..self.slots_per_page).unwrap();
+            let free = slots
+                .iter()
+                .zip(0..)
+                .filter(|(slot, _)| **slot == 0)
+                .map(|(_, slot_number)| ItemId {
+                    page_number,
+                    slot_number,
+                })
+                .collect::<Vec<_>>();
+            self.free_slots.extend(free);
+        }
+    }
+
+    fn set_free_space(&mut self, page_number: u32, free: u32) {
+        match self.free_space.get_mut(page_number as usize) {
+            Some(f) => *f = free,
+            None => {
+                assert!(page_number as usize == self.free_space.len());
+                self.free_space.push(free);
+            }
+        }
+        match free {
+            0 => self.free_pages.remove(&page_number),
+            _ => self.free_pages.insert(page_number),
+        };
+    }
+
+    /// adds a fresh empty page to the end of the file and pulls it into the pool
+    fn new_page(&mut self, sm: &mut StorageManager) -> Result<u32, VectorPoolError> {
+        let frame_idx = self.take_frame(sm)?;
+        self.frame_mut(frame_idx).fill(0);
+
+        let page_number = sm.new_page();
+        self.page_to_frame_map.insert(page_number, frame_idx);
+        self.frame_to_page[frame_idx] = Some(page_number);
+        self.dirty_pages.insert(frame_idx);
+        self.set_free_space(page_number, self.slots_per_page as u32);
+        for slot_number in (0..self.slots_per_page).rev() {
+            self.free_slots.push(ItemId {
+                slot_number: slot_number as u32,
+                page_number,
+            })
+        }
+
+        Ok(page_number)
+    }
+
+    /// makes sure `page_number` is in the pool, returning the frame its in
+    fn load_page(
+        &mut self,
+        page_number: u32,
+        sm: &mut StorageManager,
+    ) -> Result<usize, VectorPoolError> {
+        if let Some(frame_idx) = self.page_to_frame_map.get(&page_number) {
+            return Ok(*frame_idx);
+        }
+
+        let frame_idx = self.take_frame(sm)?;
+        if let Err(e) = sm.read_page(page_number, self.frame_mut(frame_idx)) {
+            // the frame didn't get anything, it's still empty
+            self.empty_frames.push(frame_idx);
+            return Err(e.into());
+        }
+        self.page_to_frame_map.insert(page_number, frame_idx);
+        self.frame_to_page[frame_idx] = Some(page_number);
+
+        let slots = self.frame(frame_idx).get(0..self.slots_per_page).unwrap();
+        let free = slots
+            .iter()
+            .zip(0..)
+            .filter(|(slot, _)| **slot == 0)
+            .map(|(_, slot_number)| ItemId {
+                page_number,
+                slot_number,
+            })
+            .collect::<Vec<_>>();
+        self.free_slots.extend(free);
+
+        Ok(frame_idx)
+    }
+
+    /// gets an empty frame, ditching a page if we have to
+    fn take_frame(&mut self, sm: &mut StorageManager) -> Result<usize, VectorPoolError> {
+        if let Some(frame_idx) = self.empty_frames.pop() {
+            return Ok(frame_idx);
+        }
+
+        let frame_idx = self.choose_to_ditch();
+        let page_number = self.frame_to_page[frame_idx].unwrap();
+        // if the write fails the page stays where it is, still dirty
+        if self.dirty_pages.contains(&frame_idx) {
+            sm.write_page(page_number, self.frame(frame_idx))?;
+            self.dirty_pages.remove(&frame_idx);
+        }
+        self.frame_to_page[frame_idx] = None;
+        self.page_to_frame_map.remove(&page_number);
+        self.free_slots.retain(|s| s.page_number != page_number);
+
+        Ok(frame_idx)
     }
 
     fn choose_to_ditch(&mut self) -> usize {
-        // lets do approx lru k for now, then see about tuning
-        todo!()
+        // TODO: lets do approx lru k, then see about tuning, this is just a
+        // clock hand for now
+        let frame_idx = self.next_victim;
+        self.next_victim = (self.next_victim + 1) % self.frame_to_page.len();
+        frame_idx
+    }
+
+    fn frame(&self, frame_idx: usize) -> &[u8] {
+        let frame_start = frame_idx * self.page_size;
+        let frame_end = frame_start + self.page_size;
+        self.pool.as_bytes().get(frame_start..frame_end).unwrap()
+    }
+
+    fn frame_mut(&mut self, frame_idx: usize) -> &mut [u8] {
+        let frame_start = frame_idx * self.page_size;
+        let frame_end = frame_start + self.page_size;
+        self.pool
+            .as_mut_bytes()
+            .get_mut(frame_start..frame_end)
+            .unwrap()
     }
 }
@@ -0,0 +1,45 @@
+// shared by the integration tests, not every test file uses all of these
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use rand::{rngs::StdRng, Rng};
+
+/// a `.tw` path in the temp dir, with anything a previous run left there
+/// (including the raw vectors file) removed
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{name}-{}.tw", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(path.with_extension("raw.tw"));
+    path
+}
+
+/// `n` vectors uniform in -1..1
+pub fn random(rng: &mut StdRng, n: usize, dim: usize) -> Vec<Vec<f32>> {
+    (0..n)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect()
+}
+
+/// tight clusters spread far apart, where linking only the closest neighbors
+/// leaves each cluster with no way out of it
+pub fn clustered(
+    rng: &mut StdRng,
+    clusters: usize,
+    per_cluster: usize,
+    dim: usize,
+) -> Vec<Vec<f32>> {
+    let mut out = vec![];
+    for _ in 0..clusters {
+        let center: Vec<f32> = (0..dim).map(|_| rng.gen_range(-100.0..100.0)).collect();
+        for _ in 0..per_cluster {
+            out.push(
+                center
+                    .iter()
+                    .map(|c| c + rng.gen_range(-1.0..1.0))
+                    .collect(),
+            );
+        }
+    }
+    out
+}
@@ -0,0 +1,19 @@
+use std::io::Write;
+
+use tinyworld::eval::{Dataset, EvalError};
+
+#[test]
+fn ragged_dataset_is_an_error() {
+    let path = std::env::temp_dir().join(format!("ragged-{}.json", std::process::id()));
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(file, r#"{{"word": "a", "emb": [1.0, 2.0, 3.0]}}"#).unwrap();
+    writeln!(file, r#"{{"word": "b", "emb": [1.0, 2.0]}}"#).unwrap();
+    drop(file);
+
+    assert!(matches!(
+        Dataset::from_jsonl(&path),
+        Err(EvalError::DimensionError { key, expected: 3, found: 2 }) if key == "b"
+    ));
+
+    std::fs::remove_file(path).unwrap();
+}
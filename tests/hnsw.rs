@@ -0,0 +1,430 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SimpleDotProduct, SquaredEuclidean},
+    flat::FlatIndex,
+    graph_stats::GraphIssue,
+    hnsw::{Error, FixedParams, HNSW, MAX_LEVEL},
+    search::{SearchOptions, StopReason},
+};
+
+mod common;
+use common::clustered;
+
+fn recall(
+    params: FixedParams,
+    data: &[Vec<f32>],
+    queries: &[Vec<f32>],
+    k: usize,
+    ef: usize,
+) -> f32 {
+    let mut flat = FlatIndex::new(
+        params.dimension,
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    let mut hnsw = HNSW::new(params, RefCell::new(Box::new(SquaredEuclidean {})));
+    let mut idx = HashMap::new();
+    for (i, d) in data.iter().enumerate() {
+        idx.insert(hnsw.insert(d).unwrap(), i);
+        idx.insert(flat.insert(d).unwrap(), i);
+    }
+
+    let mut hits = 0;
+    for q in queries {
+        let expected: Vec<usize> = flat
+            .search(q, k, 0)
+            .unwrap()
+            .iter()
+            .map(|(id, _)| idx[id])
+            .collect();
+        hits += hnsw
+            .search(q, k, ef)
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| expected.contains(&idx[id]))
+            .count();
+    }
+    hits as f32 / (queries.len() * k) as f32
+}
+
+#[test]
+fn heuristic_beats_closest_on_clusters() {
+    let mut rng = StdRng::seed_from_u64(37);
+    let data = clustered(&mut rng, 40, 50, 4);
+    let queries: Vec<Vec<f32>> = data
+        .iter()
+        .step_by(10)
+        .map(|d| d.iter().map(|x| x + rng.gen_range(-0.5..0.5)).collect())
+        .collect();
+    let params = |neighbor_heuristic| FixedParams {
+        dimension: 4,
+        m: 4,
+        m_max: 4,
+        m0_max: 8,
+        ef_construction: 40,
+        level_norm: 1.0 / f32::ln(4.0),
+        neighbor_heuristic,
+        ..Default::default()
+    };
+
+    let closest = recall(params(false), &data, &queries, 10, 10);
+    let heuristic = recall(params(true), &data, &queries, 10, 10);
+    assert!(
+        heuristic > closest,
+        "closest {closest} heuristic {heuristic}"
+    );
+}
+
+#[test]
+fn search_returns_min_of_top_k_and_len() {
+    let mut rng = StdRng::seed_from_u64(38);
+    for _ in 0..50 {
+        let dim = rng.gen_range(1..8);
+        let mut hnsw = HNSW::new(
+            FixedParams {
+                dimension: dim as u32,
+                m: rng.gen_range(2..8),
+                m_max: 8,
+                m0_max: 16,
+                ef_construction: rng.gen_range(1..20),
+                level_norm: 1.0 / f32::ln(4.0),
+                ..Default::default()
+            },
+            RefCell::new(Box::new(SquaredEuclidean {})),
+        );
+
+        let mut ids = vec![];
+        for _ in 0..rng.gen_range(0..40) {
+            let v: Vec<f32> = (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            ids.push(hnsw.insert(&v).unwrap());
+        }
+        // sometimes delete down to nearly (or completely) empty
+        let deleted = rng.gen_bool(0.3);
+        if deleted {
+            let keep = rng.gen_range(0..=usize::min(2, ids.len()));
+            for id in ids.drain(keep..) {
+                hnsw.delete(id).unwrap();
+            }
+        }
+
+        let query: Vec<f32> = (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let top_k = rng.gen_range(0..50);
+        let ef = rng.gen_range(0..50);
+        let found = hnsw.search(&query, top_k, ef).unwrap();
+
+        assert!(found.len() <= usize::min(top_k, ids.len()));
+        // ef gets clamped to top_k, so small indexes come back whole (as long as
+        // deletes didn't cut the graph apart)
+        if !deleted && ids.len() <= top_k {
+            assert_eq!(found.len(), ids.len());
+        }
+        assert!(found.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(found.iter().all(|(id, _)| ids.contains(id)));
+    }
+}
+
+#[test]
+fn range_search_matches_brute_force() {
+    let mut rng = StdRng::seed_from_u64(40);
+    let data: Vec<Vec<f32>> = (0..1000)
+        .map(|_| (0..4).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect();
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 4,
+            m: 8,
+            m_max: 16,
+            m0_max: 32,
+            ef_construction: 100,
+            level_norm: 1.0 / f32::ln(8.0),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    let mut idx = HashMap::new();
+    for (i, d) in data.iter().enumerate() {
+        idx.insert(hnsw.insert(d).unwrap(), i);
+    }
+
+    let mut dist_calc = SquaredEuclidean {};
+    let (mut expected_total, mut found_total) = (0, 0);
+    for q in data.iter().step_by(25) {
+        // a radius that takes in somewhere between less and a lot more than ef
+        let mut dists: Vec<f32> = data.iter().map(|d| dist_calc.calc_dist(q, d)).collect();
+        dists.sort_by(|a, b| a.total_cmp(b));
+        let radius = dists[rng.gen_range(5..40)];
+        let expected: Vec<usize> = (0..data.len())
+            .filter(|i| dist_calc.calc_dist(q, &data[*i]) <= radius)
+            .collect();
+        let found = hnsw.range_search(q, radius, 10).unwrap();
+
+        assert!(found.iter().all(|(_, d)| *d <= radius));
+        assert!(found.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(found.iter().all(|(id, _)| expected.contains(&idx[id])));
+        expected_total += expected.len();
+        found_total += found.len();
+    }
+    assert!(found_total as f32 >= expected_total as f32 * 0.95);
+}
+
+#[test]
+fn search_batch_matches_search() {
+    let mut rng = StdRng::seed_from_u64(41);
+    let data = clustered(&mut rng, 10, 50, 8);
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 8,
+            m: 8,
+            m_max: 16,
+            m0_max: 32,
+            ef_construction: 50,
+            level_norm: 1.0 / f32::ln(8.0),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    for d in &data {
+        hnsw.insert(d).unwrap();
+    }
+
+    let queries: Vec<Vec<f32>> = (0..100)
+        .map(|_| (0..8).map(|_| rng.gen_range(-100.0..100.0)).collect())
+        .collect();
+    let queries: Vec<&[f32]> = queries.iter().map(|q| q.as_slice()).collect();
+    let batch = hnsw.search_batch(&queries, 10, 20).unwrap();
+
+    assert_eq!(batch.len(), queries.len());
+    for (q, found) in queries.iter().zip(batch) {
+        assert_eq!(found, hnsw.search(q, 10, 20).unwrap());
+    }
+}
+
+#[test]
+fn search_stats_add_up() {
+    let mut rng = StdRng::seed_from_u64(46);
+    let data = clustered(&mut rng, 10, 50, 8);
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 8,
+            m: 8,
+            m_max: 16,
+            m0_max: 32,
+            ef_construction: 50,
+            level_norm: 1.0 / f32::ln(8.0),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    for d in &data {
+        hnsw.insert(d).unwrap();
+    }
+
+    let query = &data[123];
+    let (small, small_stats) = hnsw.search_with_stats(query, 10, 10).unwrap();
+    let (big, big_stats) = hnsw.search_with_stats(query, 10, 200).unwrap();
+    assert_eq!(small, hnsw.search(query, 10, 10).unwrap());
+    assert_eq!(big, hnsw.search(query, 10, 200).unwrap());
+
+    for stats in [&small_stats, &big_stats] {
+        // every visited node got its distance computed once
+        assert_eq!(stats.visited.iter().sum::<usize>(), stats.dist_evals);
+        assert!(stats.visited.iter().all(|v| *v > 0));
+        assert!(stats.candidates_peak > 0);
+    }
+    assert_eq!(small_stats.stop_reason, StopReason::Converged);
+    assert!(big_stats.dist_evals > small_stats.dist_evals);
+    assert!(big_stats.visited[0] > small_stats.visited[0]);
+}
+
+#[test]
+fn stats_match_the_graph() {
+    let mut rng = StdRng::seed_from_u64(47);
+    let data = clustered(&mut rng, 10, 50, 8);
+    let params = FixedParams {
+        dimension: 8,
+        m: 8,
+        m_max: 16,
+        m0_max: 32,
+        ef_construction: 50,
+        level_norm: 1.0 / f32::ln(8.0),
+        ..Default::default()
+    };
+    let mut hnsw = HNSW::new(params, RefCell::new(Box::new(SquaredEuclidean {})));
+    for d in &data {
+        hnsw.insert(d).unwrap();
+    }
+
+    assert!(hnsw.check().is_ok());
+    let stats = hnsw.stats();
+    assert_eq!(stats.nodes_per_level[0], data.len());
+    assert!(stats.nodes_per_level.windows(2).all(|w| w[0] >= w[1]));
+    for (level, degrees) in stats.degrees.iter().enumerate() {
+        assert_eq!(degrees.iter().sum::<usize>(), stats.nodes_per_level[level]);
+        assert_eq!(degrees.len(), stats.max_degree[level] + 1);
+    }
+    assert_eq!(stats.max_degree[0], 32);
+}
+
+#[test]
+fn reconnect_orphans_makes_everything_reachable() {
+    let mut rng = StdRng::seed_from_u64(48);
+    // tiny neighbor lists prune away a lot of back links
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 4,
+            m: 3,
+            m_max: 3,
+            m0_max: 3,
+            ef_construction: 10,
+            level_norm: 1.0 / f32::ln(3.0),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    for _ in 0..300 {
+        let v: Vec<f32> = (0..4).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        hnsw.insert(&v).unwrap();
+    }
+    let unreachable = |hnsw: &HNSW| {
+        hnsw.stats()
+            .issues
+            .iter()
+            .filter(|i| matches!(i, GraphIssue::Unreachable(_)))
+            .count()
+    };
+    assert!(unreachable(&hnsw) > 0);
+
+    assert!(hnsw.reconnect_orphans() > 0);
+    assert_eq!(unreachable(&hnsw), 0);
+    assert!(hnsw.check().is_ok());
+}
+
+#[test]
+fn levels_follow_the_distribution() {
+    let mut rng = StdRng::seed_from_u64(49);
+    let m = 4;
+    let n = 4000;
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 2,
+            m,
+            m_max: m,
+            m0_max: 2 * m,
+            ef_construction: 8,
+            level_norm: 1.0 / f32::ln(m as f32),
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    for _ in 0..n {
+        hnsw.insert(&[rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)])
+            .unwrap();
+    }
+
+    // a node makes it to level l or higher with chance m^-l, so each level
+    // should have about 1/m of the one below it. nodes_per_level counts every
+    // node on a level, not just the ones that stop there
+    let stats = hnsw.stats();
+    for (level, count) in stats.nodes_per_level.iter().enumerate() {
+        let p = (m as f64).powi(-(level as i32));
+        let expected = n as f64 * p;
+        let sigma = f64::sqrt(n as f64 * p * (1.0 - p));
+        assert!(
+            (*count as f64 - expected).abs() <= 4.0 * sigma + 1.0,
+            "level {level}: {count} nodes, expected about {expected:.0}"
+        );
+    }
+    assert!(stats.nodes_per_level.len() >= 4);
+}
+
+#[test]
+fn levels_are_bounded() {
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 2,
+            level_norm: 1000.0,
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    );
+    for i in 0..20 {
+        hnsw.insert(&[i as f32, 0.0]).unwrap();
+    }
+    assert!(hnsw.stats().nodes_per_level.len() <= MAX_LEVEL as usize + 1);
+}
+
+#[test]
+fn dot_product_top_hit_has_the_biggest_score() {
+    let mut hnsw = HNSW::new(
+        FixedParams {
+            dimension: 2,
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SimpleDotProduct {})),
+    );
+    let same = hnsw.insert(&[1.0, 0.0]).unwrap();
+    let opposite = hnsw.insert(&[-1.0, 0.0]).unwrap();
+    let sideways = hnsw.insert(&[0.0, 1.0]).unwrap();
+
+    assert_eq!(hnsw.search(&[1.0, 0.0], 1, 10).unwrap()[0].0, same);
+
+    let hits = hnsw
+        .search_with(&[1.0, 0.0], &SearchOptions::new(3).ef(10))
+        .unwrap();
+    let ids: Vec<_> = hits.iter().map(|h| h.id).collect();
+    assert_eq!(ids, vec![same, sideways, opposite]);
+    let scores: Vec<_> = hits.iter().map(|h| h.score).collect();
+    assert_eq!(scores, vec![1.0, 0.0, -1.0]);
+}
+
+#[test]
+fn flat_rejects_zero_dimension() {
+    let mut flat = FlatIndex::new(0, RefCell::new(Box::new(SquaredEuclidean {})));
+    assert!(flat.search(&[], 10, 0).unwrap().is_empty());
+    assert!(matches!(flat.insert(&[]), Err(Error::EmbSizeError)));
+    assert!(flat.is_empty());
+}
+
+#[test]
+fn wrong_size_queries_are_rejected() {
+    let mut rng = StdRng::seed_from_u64(38);
+    let params = FixedParams {
+        dimension: 8,
+        ..Default::default()
+    };
+    let mut hnsw = HNSW::new(params, RefCell::new(Box::new(SquaredEuclidean {})));
+    let mut flat = FlatIndex::new(8, RefCell::new(Box::new(SquaredEuclidean {})));
+    for _ in 0..20 {
+        let v: Vec<f32> = (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        hnsw.insert(&v).unwrap();
+        flat.insert(&v).unwrap();
+    }
+
+    let short = [0.5, 0.5];
+    assert!(matches!(
+        hnsw.search(&short, 3, 10),
+        Err(Error::EmbSizeError)
+    ));
+    assert!(matches!(
+        hnsw.search_with_stats(&short, 3, 10),
+        Err(Error::EmbSizeError)
+    ));
+    assert!(matches!(
+        hnsw.range_search(&short, 1.0, 10),
+        Err(Error::EmbSizeError)
+    ));
+    assert!(matches!(
+        hnsw.search_with(&short, &SearchOptions::new(3)),
+        Err(Error::EmbSizeError)
+    ));
+    let fine = [0.5; 8];
+    assert!(matches!(
+        hnsw.search_batch(&[&fine, &short], 3, 10),
+        Err(Error::EmbSizeError)
+    ));
+    assert!(matches!(
+        flat.search(&short, 3, 0),
+        Err(Error::EmbSizeError)
+    ));
+}
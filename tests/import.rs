@@ -0,0 +1,128 @@
+use std::{cell::RefCell, fs::File, path::PathBuf, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, StringArray},
+    datatypes::{DataType, Field, Schema},
+};
+use parquet::arrow::ArrowWriter;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::json;
+use tinyworld::{
+    distance_calculators::SquaredEuclidean,
+    hnsw::{FixedParams, HNSW},
+    import::{ImportError, Importer},
+};
+use uuid::Uuid;
+
+// rows of (id, embedding, title) in a parquet file, written a few rows per row
+// group so reading it takes more than one batch
+fn write_parquet(name: &str, ids: &[Uuid], vectors: &[Vec<f32>]) -> PathBuf {
+    let dim = vectors[0].len() as i32;
+    let item = Arc::new(Field::new("item", DataType::Float32, false));
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("emb", DataType::FixedSizeList(item.clone(), dim), false),
+        Field::new("title", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from_iter_values(
+                ids.iter().map(|id| id.to_string()),
+            )) as ArrayRef,
+            Arc::new(FixedSizeListArray::new(
+                item,
+                dim,
+                Arc::new(Float32Array::from_iter_values(vectors.concat())),
+                None,
+            )),
+            Arc::new(StringArray::from_iter_values(
+                (0..ids.len()).map(|i| format!("doc {i}")),
+            )),
+        ],
+    )
+    .unwrap();
+
+    let path = std::env::temp_dir().join(format!("{name}-{}.parquet", std::process::id()));
+    let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+    for start in (0..batch.num_rows()).step_by(30) {
+        writer
+            .write(&batch.slice(start, 30.min(batch.num_rows() - start)))
+            .unwrap();
+    }
+    writer.close().unwrap();
+    path
+}
+
+fn hnsw(dimension: u32) -> HNSW {
+    HNSW::new(
+        FixedParams {
+            dimension,
+            ..Default::default()
+        },
+        RefCell::new(Box::new(SquaredEuclidean {})),
+    )
+}
+
+#[test]
+fn import_parquet_maps_columns() {
+    let mut rng = StdRng::seed_from_u64(50);
+    let ids: Vec<Uuid> = (0..100).map(|_| Uuid::new_v4()).collect();
+    let vectors: Vec<Vec<f32>> = (0..100)
+        .map(|_| (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect();
+    let path = write_parquet("import", &ids, &vectors);
+
+    let mut index = hnsw(8);
+    let imported = Importer::new("emb")
+        .id_column("id")
+        .payload_column("title")
+        .batch_size(16)
+        .import_parquet(&path, &mut index)
+        .unwrap();
+    assert_eq!(imported, ids);
+    assert_eq!(index.len(), 100);
+    for (i, id) in ids.iter().enumerate() {
+        assert_eq!(index.get(*id), Some(vectors[i].as_slice()));
+        assert_eq!(
+            index.payload(*id),
+            Some(&json!({ "title": format!("doc {i}") }))
+        );
+    }
+    assert_eq!(index.search(&vectors[42], 1, 16).unwrap()[0].0, ids[42]);
+
+    // the same ids again, nothing from the batch goes in
+    assert!(matches!(
+        Importer::new("emb")
+            .id_column("id")
+            .import_parquet(&path, &mut index),
+        Err(ImportError::DuplicateId(id)) if id == ids[0]
+    ));
+    assert_eq!(index.len(), 100);
+
+    // and the same id twice in one batch
+    let twice = write_parquet("import-twice", &[ids[0], ids[1], ids[0]], &vectors[..3]);
+    let mut fresh = hnsw(8);
+    assert!(matches!(
+        Importer::new("emb")
+            .id_column("id")
+            .import_parquet(&twice, &mut fresh),
+        Err(ImportError::DuplicateId(id)) if id == ids[0]
+    ));
+    assert!(fresh.is_empty());
+    std::fs::remove_file(twice).unwrap();
+
+    assert!(matches!(
+        Importer::new("emb").import_parquet(&path, &mut hnsw(16)),
+        Err(ImportError::DimensionError {
+            expected: 16,
+            found: 8
+        })
+    ));
+    assert!(matches!(
+        Importer::new("missing").import_parquet(&path, &mut hnsw(8)),
+        Err(ImportError::ColumnError(_))
+    ));
+
+    std::fs::remove_file(path).unwrap();
+}
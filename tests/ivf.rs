@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, SeedableRng};
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SimpleDotProduct, SquaredEuclidean},
+    ivf::{IVFError, IVF},
+};
+
+mod common;
+use common::{random, temp_path};
+
+#[test]
+fn recall_and_reopen() {
+    let path = temp_path("ivf");
+    let mut rng = StdRng::seed_from_u64(35);
+    let data = random(&mut rng, 2000, 16);
+    let sample: Vec<&[f32]> = data.iter().step_by(4).map(|v| v.as_slice()).collect();
+
+    let mut ivf = IVF::create(
+        path.to_str().unwrap(),
+        16,
+        32,
+        &sample,
+        10,
+        Box::new(SquaredEuclidean {}),
+    )
+    .unwrap();
+    let mut idx = HashMap::new();
+    for (i, d) in data.iter().enumerate() {
+        idx.insert(ivf.insert(d).unwrap(), i);
+    }
+    ivf.flush().unwrap();
+
+    let queries = random(&mut rng, 50, 16);
+    let k = 10;
+    let mut hits = 0;
+    for q in &queries {
+        let mut dists: Vec<(usize, f32)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (i, SquaredEuclidean {}.calc_dist(q, d)))
+            .collect();
+        dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let expected: Vec<usize> = dists.iter().take(k).map(|(i, _)| *i).collect();
+        hits += ivf
+            .search(q, k, 8)
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| expected.contains(&idx[id]))
+            .count();
+    }
+    let recall = hits as f32 / (queries.len() * k) as f32;
+    assert!(recall > 0.7, "recall {recall}");
+
+    // every list probed is exact
+    let before = ivf.search(&queries[0], k, 32).unwrap();
+    drop(ivf);
+    assert!(matches!(
+        IVF::open(path.to_str().unwrap(), Box::new(SimpleDotProduct {})),
+        Err(IVFError::MetricMismatchError)
+    ));
+    let mut ivf = IVF::open(path.to_str().unwrap(), Box::new(SquaredEuclidean {})).unwrap();
+    assert_eq!(ivf.len(), data.len());
+    assert_eq!(ivf.nlist(), 32);
+    for (id, i) in idx.iter().take(100) {
+        assert_eq!(ivf.get(*id).unwrap(), data[*i]);
+    }
+    let after = ivf.search(&queries[0], k, 32).unwrap();
+    assert_eq!(
+        before.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        after.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,115 @@
+use rand::{rngs::StdRng, SeedableRng};
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, Hamming, SimpleDotProduct, SquaredEuclidean},
+    quantization::{Encoding, PreparedQuery, ProductQuantizer, ScalarQuantizer},
+};
+
+mod common;
+use common::random;
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() <= 1e-4 * f32::max(1.0, a.abs())
+}
+
+// encodes every vector, checks it decodes back to within `tolerance` per
+// dimension, and that the encoding's distance to the code is the distance to
+// the decoded vector for both metrics
+fn check(encoding: &Encoding, data: &[Vec<f32>], queries: &[Vec<f32>], tolerance: f32) {
+    let dim = data[0].len();
+    let mut code = vec![0; encoding.vec_size(dim)];
+    let mut decoded = vec![0.0; dim];
+    let calcs: [&mut dyn DistanceCalculator; 2] =
+        [&mut SquaredEuclidean {}, &mut SimpleDotProduct {}];
+    for dist_calc in calcs {
+        for q in queries {
+            let mut prepared = PreparedQuery::default();
+            encoding.prepare_query(q, dist_calc, &mut prepared);
+            for v in data {
+                encoding.encode(v, &mut code);
+                encoding.decode(&code, &mut decoded);
+                for (x, y) in v.iter().zip(&decoded) {
+                    assert!((x - y).abs() <= tolerance, "{x} decoded to {y}");
+                }
+
+                let dist = encoding.calc_dist(q, &prepared, &code, dist_calc);
+                let expected = dist_calc.calc_dist(q, &decoded);
+                assert!(close(dist, expected), "{dist} vs {expected}");
+            }
+        }
+    }
+
+    // and the encoder survives a trip through the file
+    let reloaded = Encoding::from_bytes(encoding.id(), dim, &encoding.to_bytes()).unwrap();
+    let mut reloaded_code = vec![0; code.len()];
+    for v in data {
+        encoding.encode(v, &mut code);
+        reloaded.encode(v, &mut reloaded_code);
+        assert_eq!(code, reloaded_code);
+    }
+}
+
+#[test]
+fn sq8() {
+    let mut rng = StdRng::seed_from_u64(28);
+    let data = random(&mut rng, 200, 32);
+    let queries = random(&mut rng, 10, 32);
+
+    for per_dim in [true, false] {
+        let sq = ScalarQuantizer::fit(data.iter().map(|v| v.as_slice()), 32, per_dim);
+        // half a step either way from rounding
+        let tolerance = sq.scales().iter().copied().fold(0.0, f32::max) / 2.0 + 1e-6;
+        check(&Encoding::SQ8(sq), &data, &queries, tolerance);
+    }
+}
+
+#[test]
+fn pq() {
+    let mut rng = StdRng::seed_from_u64(29);
+    let data = random(&mut rng, 300, 32);
+    let queries = random(&mut rng, 10, 32);
+    let sample: Vec<&[f32]> = data.iter().map(|v| v.as_slice()).collect();
+
+    // with as many centroids as points every sub vector gets one to itself, so
+    // the codes are exact
+    let pq = ProductQuantizer::train(&sample[..200], 32, 8, 5).unwrap();
+    check(&Encoding::PQ(pq), &data[..200], &queries, 1e-5);
+
+    // unseen vectors only get close, the adc distances still have to add up
+    let pq = ProductQuantizer::train(&sample[..200], 32, 8, 5).unwrap();
+    check(&Encoding::PQ(pq), &data[200..], &queries, 2.0);
+
+    assert!(ProductQuantizer::train(&sample, 32, 5, 5).is_err());
+}
+
+#[test]
+fn half_precision() {
+    let mut rng = StdRng::seed_from_u64(31);
+    let data = random(&mut rng, 100, 32);
+    let queries = random(&mut rng, 10, 32);
+
+    // 11 and 8 bits of mantissa, for values under 1
+    check(&Encoding::F16, &data, &queries, 1.0 / 2048.0);
+    check(&Encoding::BF16, &data, &queries, 1.0 / 256.0);
+}
+
+#[test]
+fn binary_matches_the_reference_hamming() {
+    let mut rng = StdRng::seed_from_u64(30);
+    // not a multiple of 64, so the tail bytes get counted too
+    let data = random(&mut rng, 100, 70);
+    let queries = random(&mut rng, 10, 70);
+
+    let encoding = Encoding::Binary;
+    let mut code = vec![0; encoding.vec_size(70)];
+    for q in &queries {
+        let mut prepared = PreparedQuery::default();
+        encoding.prepare_query(q, &mut SquaredEuclidean {}, &mut prepared);
+        for v in &data {
+            encoding.encode(v, &mut code);
+            assert_eq!(
+                encoding.calc_dist(q, &prepared, &code, &mut SquaredEuclidean {}),
+                Hamming {}.calc_dist(q, v)
+            );
+        }
+    }
+}
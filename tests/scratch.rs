@@ -1,4 +1,27 @@
+use tinyworld::{distance_calculators::SimpleDotProduct, hnsw::FixedParams, tinyworld::TinyWorld};
+
 #[test]
 fn scratch() {
-    let sm = StorageManager::create("scratch.tw");
+    let path = std::env::temp_dir().join(format!("scratch-{}.tw", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut tw = TinyWorld::create(
+        path.to_str().unwrap(),
+        FixedParams {
+            dimension: 4,
+            ..Default::default()
+        },
+        Box::new(SimpleDotProduct {}),
+    )
+    .unwrap();
+
+    let id = tw.insert(&[1.0, 0.0, 0.0, 0.0]).unwrap();
+    tw.insert(&[0.0, 1.0, 0.0, 0.0]).unwrap();
+    tw.flush().unwrap();
+
+    let found = tw.search(&[1.0, 0.0, 0.0, 0.0], 2, 10).unwrap();
+    assert_eq!(found.len(), 2);
+    assert!(found.iter().any(|(f, _)| *f == id));
+
+    std::fs::remove_file(&path).unwrap();
 }
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SimpleDotProduct, SquaredEuclidean},
+    hnsw::FixedParams,
+    quantization::Encoding,
+    tinyworld::{TWError, TinyWorld},
+};
+
+mod common;
+use common::{clustered, random, temp_path};
+
+#[test]
+fn binary_needs_raw_vectors() {
+    let path = temp_path("binary");
+    let params = || FixedParams {
+        dimension: 8,
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        TinyWorld::create_with_encoding(
+            path.to_str().unwrap(),
+            params(),
+            Box::new(SquaredEuclidean {}),
+            Encoding::Binary,
+            false,
+        ),
+        Err(TWError::ParamsError)
+    ));
+
+    let mut tw = TinyWorld::create_with_encoding(
+        path.to_str().unwrap(),
+        params(),
+        Box::new(SquaredEuclidean {}),
+        Encoding::Binary,
+        true,
+    )
+    .unwrap();
+    let v = [0.5, -1.0, 2.0, 0.25, -0.5, 1.5, -2.0, 1.0];
+    let id = tw.insert(&v).unwrap();
+    tw.insert(&v.map(|x| x + 0.1)).unwrap();
+    // re-ranked with the real metric, not the (zero) hamming distance
+    let found = tw.search(&v.map(|x| x + 0.05), 2, 10).unwrap();
+    assert_eq!(found[0].0, id);
+    assert!((found[0].1 - 8.0 * 0.05 * 0.05).abs() < 1e-5);
+
+    std::fs::remove_file(path.with_extension("raw.tw")).unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn compact_and_reopen() {
+    let path = temp_path("compact");
+    // none of these are the defaults
+    let params = FixedParams {
+        dimension: 16,
+        ef_construction: 80,
+        neighbor_heuristic: false,
+        extend_candidates: true,
+        keep_pruned_connections: false,
+        ..Default::default()
+    };
+    let mut rng = StdRng::seed_from_u64(27);
+    let mut tw = TinyWorld::create(
+        path.to_str().unwrap(),
+        params,
+        Box::new(SquaredEuclidean {}),
+    )
+    .unwrap();
+
+    let mut live = HashMap::new();
+    let mut dead = vec![];
+    for i in 0..600 {
+        let v: Vec<f32> = (0..16).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let id = tw.insert(&v).unwrap();
+        // keep every third one, spread over all the pages
+        match i % 3 {
+            0 => {
+                live.insert(id, v);
+            }
+            _ => dead.push(id),
+        }
+    }
+    for id in dead {
+        tw.delete(id).unwrap();
+    }
+    tw.flush().unwrap();
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    let moved = tw.compact().unwrap();
+    assert!(!moved.is_empty());
+    let live: HashMap<_, _> = live
+        .into_iter()
+        .map(|(id, v)| (*moved.get(&id).unwrap_or(&id), v))
+        .collect();
+    drop(tw);
+    assert!(std::fs::metadata(&path).unwrap().len() < before);
+
+    assert!(matches!(
+        TinyWorld::open(path.to_str().unwrap(), Box::new(SimpleDotProduct {})),
+        Err(TWError::MetricMismatchError)
+    ));
+
+    let mut tw = TinyWorld::open(path.to_str().unwrap(), Box::new(SquaredEuclidean {})).unwrap();
+    assert_eq!(tw.len(), 200);
+    let reopened = tw.fixed_params();
+    assert_eq!(reopened.ef_construction, 80);
+    assert!(!reopened.neighbor_heuristic);
+    assert!(reopened.extend_candidates);
+    assert!(!reopened.keep_pruned_connections);
+    let mut found = 0;
+    for (id, v) in &live {
+        assert_eq!(&tw.get(*id).unwrap(), v);
+        let hits = tw.search(v, 1, 50).unwrap();
+        assert!(live.contains_key(&hits[0].0));
+        if hits[0].0 == *id {
+            found += 1;
+        }
+    }
+    assert!(found >= 190, "only found {found} of 200");
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn truncated_file_is_an_error() {
+    let path = temp_path("truncated");
+    let mut tw = TinyWorld::create(
+        path.to_str().unwrap(),
+        FixedParams {
+            dimension: 16,
+            ..Default::default()
+        },
+        Box::new(SquaredEuclidean {}),
+    )
+    .unwrap();
+    let mut rng = StdRng::seed_from_u64(27);
+    for _ in 0..300 {
+        let v: Vec<f32> = (0..16).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        tw.insert(&v).unwrap();
+    }
+    tw.flush().unwrap();
+    drop(tw);
+
+    // cut off in the middle of the pages, and then in the middle of the header
+    let len = std::fs::metadata(&path).unwrap().len();
+    for cut in [len / 2, 8] {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(cut)
+            .unwrap();
+        assert!(TinyWorld::open(path.to_str().unwrap(), Box::new(SquaredEuclidean {})).is_err());
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn compact_checks_the_raw_file_first() {
+    // the same vectors in both, with different ones deleted
+    let mut rng = StdRng::seed_from_u64(27);
+    let vectors = random(&mut rng, 60, 16);
+    let paths = [temp_path("mismatch-a"), temp_path("mismatch-b")];
+    for (path, keep) in paths.iter().zip([0, 1]) {
+        let mut tw = TinyWorld::create_with_encoding(
+            path.to_str().unwrap(),
+            FixedParams {
+                dimension: 16,
+                ..Default::default()
+            },
+            Box::new(SquaredEuclidean {}),
+            Encoding::F16,
+            true,
+        )
+        .unwrap();
+        let ids: Vec<_> = vectors.iter().map(|v| tw.insert(v).unwrap()).collect();
+        for (i, id) in ids.into_iter().enumerate() {
+            if i % 2 != keep {
+                tw.delete(id).unwrap();
+            }
+        }
+        tw.flush().unwrap();
+    }
+    let [a, b] = paths;
+    std::fs::copy(b.with_extension("raw.tw"), a.with_extension("raw.tw")).unwrap();
+    let before = std::fs::read(&a).unwrap();
+
+    let mut tw = TinyWorld::open(a.to_str().unwrap(), Box::new(SquaredEuclidean {})).unwrap();
+    assert!(matches!(tw.compact(), Err(TWError::RawMismatchError)));
+    // nothing moved, in memory or on disk
+    assert_eq!(tw.len(), 30);
+    assert!(tw.check().is_ok());
+    drop(tw);
+    assert_eq!(std::fs::read(&a).unwrap(), before);
+
+    for path in [a, b] {
+        std::fs::remove_file(path.with_extension("raw.tw")).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn heuristic_beats_closest_on_clusters() {
+    let mut rng = StdRng::seed_from_u64(26);
+    let data = clustered(&mut rng, 40, 50, 4);
+    let queries: Vec<Vec<f32>> = data
+        .iter()
+        .step_by(10)
+        .map(|d| d.iter().map(|x| x + rng.gen_range(-0.5..0.5)).collect())
+        .collect();
+
+    let recall = |neighbor_heuristic| {
+        let path = temp_path(&format!("heuristic-{neighbor_heuristic}"));
+        let mut tw = TinyWorld::create(
+            path.to_str().unwrap(),
+            FixedParams {
+                dimension: 4,
+                m: 4,
+                m_max: 4,
+                m0_max: 8,
+                ef_construction: 40,
+                level_norm: 1.0 / f32::ln(4.0),
+                neighbor_heuristic,
+                ..Default::default()
+            },
+            Box::new(SquaredEuclidean {}),
+        )
+        .unwrap();
+        let mut idx = HashMap::new();
+        for (i, d) in data.iter().enumerate() {
+            idx.insert(tw.insert(d).unwrap(), i);
+        }
+
+        let mut hits = 0;
+        for q in &queries {
+            let mut dists: Vec<(usize, f32)> = data
+                .iter()
+                .enumerate()
+                .map(|(i, d)| (i, SquaredEuclidean {}.calc_dist(q, d)))
+                .collect();
+            dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+            let expected: Vec<usize> = dists.iter().take(10).map(|(i, _)| *i).collect();
+            hits += tw
+                .search(q, 10, 10)
+                .unwrap()
+                .iter()
+                .filter(|(id, _)| expected.contains(&idx[id]))
+                .count();
+        }
+        std::fs::remove_file(path).unwrap();
+        hits as f32 / (queries.len() * 10) as f32
+    };
+
+    let closest = recall(false);
+    let heuristic = recall(true);
+    assert!(
+        heuristic > closest,
+        "closest {closest} heuristic {heuristic}"
+    );
+}
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, path::Path};
+
+use rand::{rngs::StdRng, SeedableRng};
+use tinyworld::{
+    distance_calculators::{DistanceCalculator, SimpleDotProduct, SquaredEuclidean},
+    quantization::Encoding,
+    vamana::{Vamana, VamanaError, VamanaParams},
+};
+
+mod common;
+use common::{random, temp_path};
+
+fn create(path: &Path) -> Vamana {
+    Vamana::create(
+        path.to_str().unwrap(),
+        VamanaParams {
+            dimension: 16,
+            r: 16,
+            l_build: 50,
+            ..Default::default()
+        },
+        Box::new(SquaredEuclidean {}),
+        Encoding::F32,
+    )
+    .unwrap()
+}
+
+#[test]
+fn recall_and_reopen() {
+    let path = temp_path("vamana");
+    let mut rng = StdRng::seed_from_u64(36);
+    let data = random(&mut rng, 1000, 16);
+    let queries = random(&mut rng, 50, 16);
+
+    let mut vamana = create(&path);
+    let mut idx = HashMap::new();
+    for (i, d) in data.iter().enumerate() {
+        idx.insert(vamana.insert(d).unwrap(), i);
+    }
+    vamana.flush().unwrap();
+
+    let k = 10;
+    let mut hits = 0;
+    for q in &queries {
+        let mut dists: Vec<(usize, f32)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (i, SquaredEuclidean {}.calc_dist(q, d)))
+            .collect();
+        dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let expected: Vec<usize> = dists.iter().take(k).map(|(i, _)| *i).collect();
+        hits += vamana
+            .search(q, k, 50)
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| expected.contains(&idx[id]))
+            .count();
+    }
+    let recall = hits as f32 / (queries.len() * k) as f32;
+    assert!(recall > 0.9, "recall {recall}");
+
+    let before = vamana.search(&queries[0], k, 50).unwrap();
+    drop(vamana);
+    assert!(matches!(
+        Vamana::open(path.to_str().unwrap(), Box::new(SimpleDotProduct {})),
+        Err(VamanaError::MetricMismatchError)
+    ));
+    let mut vamana = Vamana::open(path.to_str().unwrap(), Box::new(SquaredEuclidean {})).unwrap();
+    assert_eq!(vamana.len(), data.len());
+    for (id, i) in idx.iter().take(100) {
+        assert_eq!(vamana.get(*id).unwrap(), data[*i]);
+    }
+    assert_eq!(vamana.search(&queries[0], k, 50).unwrap(), before);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn search_skips_deleted() {
+    let path = temp_path("vamana-deleted");
+    let mut rng = StdRng::seed_from_u64(360);
+    let data = random(&mut rng, 200, 16);
+
+    let mut vamana = create(&path);
+    let ids: Vec<_> = data.iter().map(|d| vamana.insert(d).unwrap()).collect();
+    for id in ids.iter().step_by(2) {
+        vamana.delete(*id).unwrap();
+    }
+    assert_eq!(vamana.len(), 100);
+    assert!(matches!(vamana.delete(ids[0]), Err(VamanaError::InvalidId)));
+
+    for (i, d) in data.iter().enumerate() {
+        let found = vamana.search(d, 10, 50).unwrap();
+        assert_eq!(found.len(), 10);
+        assert!(found
+            .iter()
+            .all(|(id, _)| ids.iter().position(|x| x == id).unwrap() % 2 == 1));
+        if i % 2 == 1 {
+            assert_eq!(found[0].0, ids[i]);
+        }
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn l_build_has_to_fit_the_header() {
+    let path = temp_path("vamana-params");
+    assert!(matches!(
+        Vamana::create(
+            path.to_str().unwrap(),
+            VamanaParams {
+                dimension: 16,
+                l_build: 300,
+                ..Default::default()
+            },
+            Box::new(SquaredEuclidean {}),
+            Encoding::F32,
+        ),
+        Err(VamanaError::ParamsError)
+    ));
+}